@@ -0,0 +1,52 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! An opt-in startup preflight check for the Linux capabilities sandboxfs needs.
+//!
+//! Without this, a missing capability is only discovered when the specific operation that needs it
+//! first runs: `create_as` chowns the freshly-created node and, on failure, has to delete the
+//! half-created node again, and a bare FUSE mount simply refuses to come up with a terse kernel
+//! error. Operators who rely on kernel `default_permissions` and run fully unprivileged never hit
+//! either case, so this check is opt-in rather than unconditional.
+
+use caps::{CapSet, Capability};
+use failure::{Fallible, ResultExt};
+use Mapping;
+
+/// Verifies that the running process holds the capabilities it will need to honor `mappings`,
+/// failing fast with a precise message instead of letting individual operations fail later.
+///
+/// `CAP_SYS_ADMIN` is required to mount a FUSE file system at all. `CAP_CHOWN` and `CAP_FOWNER` are
+/// additionally required for every writable mapping, because nodes created under it may end up
+/// chowned to a uid/gid other than the process's own.
+pub fn check_capabilities(mappings: &[Mapping]) -> Fallible<()> {
+    let effective = caps::read(None, CapSet::Effective)
+        .context("Failed to query the process' effective capability set")?;
+
+    ensure!(effective.contains(&Capability::CAP_SYS_ADMIN),
+        "Missing CAP_SYS_ADMIN, which is required to mount a FUSE file system");
+
+    for mapping in mappings {
+        if mapping.writable() {
+            ensure!(effective.contains(&Capability::CAP_CHOWN),
+                "Missing CAP_CHOWN, required because mapping '{}' is writable and may need to \
+                create nodes owned by another uid", mapping);
+            ensure!(effective.contains(&Capability::CAP_FOWNER),
+                "Missing CAP_FOWNER, required because mapping '{}' is writable and may need to \
+                create nodes owned by another gid", mapping);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! An optional content-addressed cache for regular file reads.
+//!
+//! Large sandboxes frequently map the same underlying content under many different sandbox paths
+//! (for example, several read-only mappings that all point at parts of the same build output).
+//! Without this cache, each mapped path keeps its own copy of whatever gets read into memory; with
+//! it, the bytes for a given digest are stored exactly once regardless of how many paths resolve to
+//! that content, much like a content-addressed object store.  The cache key is the SHA-256 digest
+//! of the file's bytes, and entries are invalidated by the `(mtime, size)` pair of the backing file
+//! so edits to read-write mappings never serve stale data.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The `(mtime, size)` pair used to decide whether a cached entry still matches its backing file.
+type Freshness = (i64, u64);
+
+/// A single cached entry: the bytes for a digest, alongside the freshness key they were read under.
+struct Entry {
+    freshness: Freshness,
+    data: Vec<u8>,
+}
+
+/// A bounded, content-addressed cache of file contents.
+///
+/// The cache is keyed by the SHA-256 digest of a file's bytes, so identical content mapped under
+/// different sandbox paths is only ever stored once.  It is bounded by total bytes held: new content
+/// that would exceed the bound is simply not cached, and entries already stored are never evicted.
+pub struct ContentCache {
+    max_bytes: usize,
+    state: Mutex<State>,
+}
+
+/// Mutable state protected by a single lock.
+struct State {
+    used_bytes: usize,
+    entries: HashMap<[u8; 32], Entry>,
+}
+
+impl ContentCache {
+    /// Creates a new cache that will hold at most `max_bytes` of content at once.
+    pub fn new(max_bytes: usize) -> Self {
+        ContentCache {
+            max_bytes,
+            state: Mutex::from(State { used_bytes: 0, entries: HashMap::new() }),
+        }
+    }
+
+    /// Computes the digest for `data`.
+    fn digest_of(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let result = hasher.finalize();
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&result);
+        digest
+    }
+
+    /// Records `data`, which was just freshly read under the given `freshness` key, and returns the
+    /// digest it was stored under.
+    ///
+    /// If the cache already holds an entry for this digest, the existing bytes are kept and only
+    /// the freshness key is refreshed; this is what lets multiple paths with identical content share
+    /// a single stored copy.
+    pub fn insert(&self, freshness: Freshness, data: Vec<u8>) -> [u8; 32] {
+        let digest = Self::digest_of(&data);
+        let mut state = self.state.lock().unwrap();
+        match state.entries.get_mut(&digest) {
+            Some(entry) => {
+                entry.freshness = freshness;
+            },
+            None => {
+                let len = data.len();
+                if state.used_bytes + len > self.max_bytes {
+                    // Keep things simple: if we would overflow the bound, don't cache this entry at
+                    // all rather than implementing a full eviction policy.
+                    return digest;
+                }
+                state.used_bytes += len;
+                state.entries.insert(digest, Entry { freshness, data });
+            },
+        }
+        digest
+    }
+
+    /// Returns the cached bytes for `digest` if present and still fresh under `freshness`.
+    pub fn get(&self, digest: &[u8; 32], freshness: Freshness) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        match state.entries.get(digest) {
+            Some(entry) if entry.freshness == freshness => Some(entry.data.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_get_roundtrip() {
+        let cache = ContentCache::new(1024);
+        let digest = cache.insert((100, 3), vec![1, 2, 3]);
+        assert_eq!(Some(vec![1, 2, 3]), cache.get(&digest, (100, 3)));
+    }
+
+    #[test]
+    fn get_misses_on_stale_freshness() {
+        let cache = ContentCache::new(1024);
+        let digest = cache.insert((100, 3), vec![1, 2, 3]);
+        assert_eq!(None, cache.get(&digest, (200, 3)));
+    }
+
+    #[test]
+    fn identical_content_is_stored_once() {
+        let cache = ContentCache::new(1024);
+        let digest_a = cache.insert((100, 3), vec![1, 2, 3]);
+        let digest_b = cache.insert((200, 3), vec![1, 2, 3]);
+        assert_eq!(digest_a, digest_b);
+
+        // Only the latest freshness key is retained for shared content.
+        assert_eq!(None, cache.get(&digest_a, (100, 3)));
+        assert_eq!(Some(vec![1, 2, 3]), cache.get(&digest_a, (200, 3)));
+    }
+
+    #[test]
+    fn insert_beyond_bound_is_not_cached() {
+        let cache = ContentCache::new(2);
+        let digest = cache.insert((100, 3), vec![1, 2, 3]);
+        assert_eq!(None, cache.get(&digest, (100, 3)));
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Support for serializing a mounted sandbox tree to a tar archive (the `--dump-tar` mode).
+//!
+//! This walks the in-memory node tree -- the merged view of every mapping, exactly as a FUSE
+//! client would see it -- and streams it out as a tar file.  Every file type that `AllFileTypes`
+//! exercises is handled: directories are recursed into, regular files are streamed by content,
+//! symlinks are stored as link entries, and block/char devices and FIFOs are stored with their
+//! proper tar entry type.  Sockets cannot be represented in a tar archive, so they are either
+//! skipped with a warning or abort the dump, as `OnSocket` selects.
+
+use failure::{Fallible, ResultExt};
+use fuse;
+use nodes;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tar;
+
+/// What to do when the tree being dumped contains a socket, which cannot be represented in tar.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OnSocket {
+    /// Skip the socket and log a warning, but keep dumping the rest of the tree.
+    Skip,
+
+    /// Abort the dump with an error as soon as a socket is encountered.
+    Fail,
+}
+
+/// Serializes the tree rooted at `root` into `writer` as a tar archive.
+pub fn dump_tar(root: &nodes::ArcNode, writer: impl Write, on_socket: OnSocket) -> Fallible<()> {
+    let mut builder = tar::Builder::new(writer);
+    dump_node(&mut builder, &PathBuf::from(""), root, on_socket)?;
+    builder.finish().context("Failed to finalize tar archive")?;
+    Ok(())
+}
+
+/// Appends `node`, found at `path` within the sandbox, and all of its descendants to `builder`.
+fn dump_node(builder: &mut tar::Builder<impl Write>, path: &Path, node: &nodes::ArcNode,
+    on_socket: OnSocket) -> Fallible<()> {
+    let attr = node.getattr().context(format!("Failed to stat {:?} while dumping", path))?;
+
+    match attr.kind {
+        fuse::FileType::Directory => {
+            if !path.as_os_str().is_empty() {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(u32::from(attr.perm));
+                header.set_uid(u64::from(attr.uid));
+                header.set_gid(u64::from(attr.gid));
+                header.set_size(0);
+                header.set_cksum();
+                builder.append_data(&mut header, path, &mut std::io::empty())?;
+            }
+
+            let children = node.list()
+                .context(format!("Failed to list {:?} while dumping", path))?;
+            for (name, child) in children {
+                dump_node(builder, &path.join(&name), &child, on_socket)?;
+            }
+        },
+
+        fuse::FileType::RegularFile => {
+            let handle = node.open(libc_o_rdonly())
+                .context(format!("Failed to open {:?} while dumping", path))?;
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(u32::from(attr.perm));
+            header.set_uid(u64::from(attr.uid));
+            header.set_gid(u64::from(attr.gid));
+            header.set_size(attr.size);
+            header.set_cksum();
+            let mut remaining = attr.size;
+            let mut contents = Vec::with_capacity(attr.size as usize);
+            let mut offset: i64 = 0;
+            const CHUNK: u32 = 128 * 1024;
+            while remaining > 0 {
+                let want = std::cmp::min(remaining, u64::from(CHUNK)) as u32;
+                let data = handle.read(offset, want)
+                    .context(format!("Failed to read {:?} while dumping", path))?;
+                if data.is_empty() {
+                    break;
+                }
+                offset += data.len() as i64;
+                remaining -= data.len() as u64;
+                contents.extend_from_slice(&data);
+            }
+            builder.append_data(&mut header, path, contents.as_slice())?;
+        },
+
+        fuse::FileType::Symlink => {
+            let target = node.readlink()
+                .context(format!("Failed to readlink {:?} while dumping", path))?;
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(u32::from(attr.perm));
+            header.set_size(0);
+            header.set_cksum();
+            builder.append_link(&mut header, path, &target)?;
+        },
+
+        fuse::FileType::NamedPipe | fuse::FileType::CharDevice | fuse::FileType::BlockDevice => {
+            let entry_type = match attr.kind {
+                fuse::FileType::NamedPipe => tar::EntryType::Fifo,
+                fuse::FileType::CharDevice => tar::EntryType::Char,
+                fuse::FileType::BlockDevice => tar::EntryType::Block,
+                _ => unreachable!(),
+            };
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(u32::from(attr.perm));
+            header.set_uid(u64::from(attr.uid));
+            header.set_gid(u64::from(attr.gid));
+            header.set_size(0);
+            if entry_type != tar::EntryType::Fifo {
+                header.set_device_major(major(attr.rdev))?;
+                header.set_device_minor(minor(attr.rdev))?;
+            }
+            header.set_cksum();
+            builder.append_data(&mut header, path, &mut std::io::empty())?;
+        },
+
+        fuse::FileType::Socket => {
+            match on_socket {
+                OnSocket::Skip => warn!("Skipping socket {:?}; cannot be represented in tar", path),
+                OnSocket::Fail => bail!("Cannot dump socket {:?} into a tar archive", path),
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Returns the `major(3)` device number out of a combined `st_rdev` value.
+fn major(rdev: u32) -> u32 {
+    (rdev >> 8) & 0xfff
+}
+
+/// Returns the `minor(3)` device number out of a combined `st_rdev` value.
+///
+/// Follows glibc's encoding: the low 8 bits plus the high 12 bits (bits 20-31), which `rdev & 0xff`
+/// alone would truncate away for any device whose minor number doesn't fit in 8 bits.
+fn minor(rdev: u32) -> u32 {
+    (rdev & 0xff) | ((rdev >> 12) & 0xfff00)
+}
+
+/// Returns the raw `O_RDONLY` flags value to pass to `Node::open` when dumping file contents.
+fn libc_o_rdonly() -> u32 {
+    0
+}
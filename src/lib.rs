@@ -28,13 +28,20 @@
 // increases readability.
 #![allow(clippy::redundant_field_names)]
 
+extern crate caps;
 #[cfg(feature = "profiling")] extern crate cpuprofiler;
 #[macro_use] extern crate failure;
 extern crate fuse;
+extern crate libc;
 #[macro_use] extern crate log;
+extern crate memmap2;
 extern crate nix;
-extern crate serde_derive;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
 extern crate signal_hook;
+extern crate tar;
 #[cfg(test)] extern crate tempfile;
 #[cfg(test)] extern crate users;
 extern crate time;
@@ -56,15 +63,27 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use time::Timespec;
 
+mod caps_check;
 mod concurrent;
+mod content_cache;
+mod dump_tar;
 mod nodes;
+mod oci;
+mod path_safety;
 mod profiling;
 mod reconfig;
+mod retry;
 #[cfg(test)] mod testutils;
+mod xattr_policy;
 
+pub use content_cache::ContentCache;
+pub use dump_tar::{dump_tar, OnSocket};
 pub use nodes::{ArcCache, NoCache, PathCache};
+pub use oci::mount_from_oci_spec;
 pub use profiling::ScopedProfiler;
 pub use reconfig::{open_input, open_output};
+pub use retry::RetryPolicy;
+pub use xattr_policy::{XattrPolicy, XattrRule};
 
 /// An error indicating that a mapping specification (coming from the command line or from a
 /// reconfiguration operation) is invalid.
@@ -99,11 +118,12 @@ pub fn flatten_causes(err: &Error) -> String {
 
 /// Mapping describes how an individual path within the sandbox is connected to an external path
 /// in the underlying file system.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Mapping {
     path: PathBuf,
     underlying_path: PathBuf,
     writable: bool,
+    xattr_policy: XattrPolicy,
 }
 impl Mapping {
     /// Creates a new mapping from the individual components.
@@ -135,13 +155,25 @@ impl Mapping {
             return Err(MappingError::PathNotAbsolute { path: underlying_path });
         }
 
-        Ok(Mapping { path, underlying_path, writable })
+        Ok(Mapping { path, underlying_path, writable, xattr_policy: XattrPolicy::default() })
     }
 
     /// Returns true if this is a mapping for the root directory.
     fn is_root(&self) -> bool {
         self.path.parent().is_none()
     }
+
+    /// Returns whether this mapping is writable.
+    pub(crate) fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Replaces this mapping's extended-attribute namespace policy, which otherwise defaults to
+    /// allowing every namespace through unfiltered.
+    pub fn with_xattr_policy(mut self, xattr_policy: XattrPolicy) -> Self {
+        self.xattr_policy = xattr_policy;
+        self
+    }
 }
 
 impl fmt::Display for Mapping {
@@ -194,11 +226,19 @@ struct SandboxFS {
     /// Cache of sandboxfs nodes indexed by their underlying path.
     cache: ArcCache,
 
+    /// Content-addressed cache of file bytes, shared by every node/handle backed by this instance,
+    /// or `None` if disabled at mount time.  Not to be confused with `cache` above, which caches
+    /// nodes by path rather than file contents by digest.
+    content_cache: Option<Arc<ContentCache>>,
+
     /// How long to tell the kernel to cache file metadata for.
     ttl: Timespec,
 
     /// Whether support for xattrs is enabled or not.
     xattrs: bool,
+
+    /// Governs how `unlink` and reconfiguration's `unmap` retry on a transient `EBUSY`/`ENOTEMPTY`.
+    retry_policy: retry::RetryPolicy,
 }
 
 /// A view of a `SandboxFS` instance to allow for concurrent reconfigurations.
@@ -215,6 +255,9 @@ struct ReconfigurableSandboxFS {
 
     /// Cache of sandboxfs nodes indexed by their underlying path.
     cache: ArcCache,
+
+    /// Governs how reconfiguration's `unmap` retries on a transient `EBUSY`/`ENOTEMPTY`.
+    retry_policy: retry::RetryPolicy,
 }
 
 /// Applies a mapping to the given root node.
@@ -231,26 +274,31 @@ fn apply_mapping(mapping: &Mapping, root: &dyn nodes::Node, ids: &IdGenerator,
     // any path components in the given mapping, it means we are trying to remap that same node.
     ensure!(!components.is_empty(), "Root can be mapped at most once");
 
-    root.map(components, &mapping.underlying_path, mapping.writable, &ids, cache)
+    root.map(components, &mapping.underlying_path, mapping.writable, &mapping.xattr_policy, &ids,
+        cache)
 }
 
-/// Creates the initial node hierarchy based on a collection of `mappings`.
-fn create_root(mappings: &[Mapping], ids: &IdGenerator, cache: &dyn nodes::Cache)
+/// Creates the initial node hierarchy based on the root mappings supplied by `source`.
+///
+/// `source` also governs how the root mapping itself is resolved into a node: the disk-backed
+/// `DiskNodeSource` `stat`s `first.underlying_path` and constructs a `Dir` from it exactly as
+/// before, but a synthetic `NodeSource` is free to hand back any directory node it likes.  A root
+/// that does not resolve to a directory is rejected.
+fn create_root(source: &dyn nodes::NodeSource, ids: &IdGenerator, cache: &dyn nodes::Cache)
     -> Fallible<nodes::ArcNode> {
     let now = time::get_time();
+    let mappings = source.root_mappings();
 
     let (root, rest) = if mappings.is_empty() {
         (nodes::Dir::new_empty(ids.next(), None, now), mappings)
     } else {
         let first = &mappings[0];
         if first.is_root() {
-            let fs_attr = fs::symlink_metadata(&first.underlying_path)
-                .context(format!("Failed to map root: stat failed for {:?}",
-                                 &first.underlying_path))?;
-            ensure!(fs_attr.is_dir(), "Failed to map root: {:?} is not a directory",
-                    &first.underlying_path);
-            (nodes::Dir::new_mapped(ids.next(), &first.underlying_path, &fs_attr, first.writable),
-                &mappings[1..])
+            let root = source.resolve(&first.underlying_path, ids.next(), first.writable)
+                .context(format!("Failed to map root: {:?}", &first.underlying_path))?;
+            ensure!(root.file_type_cached() == fuse::FileType::Directory,
+                "Failed to map root: {:?} is not a directory", &first.underlying_path);
+            (root, &mappings[1..])
         } else {
             (nodes::Dir::new_empty(ids.next(), None, now), mappings)
         }
@@ -266,12 +314,20 @@ fn create_root(mappings: &[Mapping], ids: &IdGenerator, cache: &dyn nodes::Cache
 
 impl SandboxFS {
     /// Creates a new `SandboxFS` instance.
-    fn create(mappings: &[Mapping], ttl: Timespec, cache: ArcCache, xattrs: bool)
+    ///
+    /// `hardened` is forwarded to the `DiskNodeSource` that backs this instance's nodes; see
+    /// `DiskNodeSource::new` for what it guards against.  `content_cache_max_bytes`, when given,
+    /// enables the shared file-content cache (see `ContentCache`) bounded to that many bytes;
+    /// `None` disables it, matching historical behavior.  `retry_policy` governs how `unlink` and
+    /// reconfiguration's `unmap` retry on a transient `EBUSY`/`ENOTEMPTY`.
+    fn create(mappings: &[Mapping], ttl: Timespec, cache: ArcCache, xattrs: bool, hardened: bool,
+        content_cache_max_bytes: Option<usize>, retry_policy: retry::RetryPolicy)
         -> Fallible<SandboxFS> {
         let ids = IdGenerator::new(fuse::FUSE_ROOT_ID);
+        let source = nodes::DiskNodeSource::new(mappings.to_vec(), hardened);
 
         let mut nodes = HashMap::new();
-        let root = create_root(mappings, &ids, cache.as_ref())?;
+        let root = create_root(&source, &ids, cache.as_ref())?;
         assert_eq!(fuse::FUSE_ROOT_ID, root.inode());
         nodes.insert(root.inode(), root);
 
@@ -280,8 +336,10 @@ impl SandboxFS {
             nodes: Arc::from(Mutex::from(nodes)),
             handles: Arc::from(Mutex::from(HashMap::new())),
             cache: cache,
+            content_cache: content_cache_max_bytes.map(|bytes| Arc::new(ContentCache::new(bytes))),
             ttl: ttl,
             xattrs: xattrs,
+            retry_policy: retry_policy,
         })
     }
 
@@ -291,6 +349,7 @@ impl SandboxFS {
             root: self.find_node(fuse::FUSE_ROOT_ID),
             ids: self.ids.clone(),
             cache: self.cache.clone(),
+            retry_policy: self.retry_policy,
         }
     }
 
@@ -337,6 +396,14 @@ impl SandboxFS {
     fn open_common(&mut self, inode: u64, flags: u32, reply: fuse::ReplyOpen) {
         let node = self.find_node(inode);
 
+        let flags = match sanitize_open_flags(flags, node.writable()) {
+            Ok(flags) => flags,
+            Err(e) => {
+                reply.error(e.errno_as_i32());
+                return;
+            },
+        };
+
         match node.open(flags) {
             Ok(handle) => {
                 let fh = self.insert_handle(handle);
@@ -394,6 +461,113 @@ fn create_as<T, E: From<Errno> + fmt::Display, P: AsRef<Path>>(
     Ok(result)
 }
 
+/// Open flags that request write access and must never reach the underlying `open` on a
+/// non-writable node.
+const WRITE_INTENT_FLAGS: i32 = libc::O_WRONLY | libc::O_RDWR | libc::O_APPEND | libc::O_TRUNC
+    | libc::O_CREAT;
+
+/// Portable bits we pass through to the underlying `open` unchanged, beyond the access mode.
+const PASSTHROUGH_FLAGS: i32 = libc::O_DIRECT | libc::O_NONBLOCK | libc::O_SYNC | libc::O_NOFOLLOW
+    | libc::O_CLOEXEC;
+
+/// Validates and translates the raw `flags` the kernel passed to `open`/`opendir` before they are
+/// allowed to reach `Node::open`.
+///
+/// This is the single place that audits what the kernel is asking for: it rejects write-intent
+/// access modes (`O_WRONLY`, `O_RDWR`, `O_APPEND`, `O_TRUNC`, `O_CREAT`) with `EROFS` when `writable`
+/// is false, and otherwise passes through only the access mode, the write-intent bits above (which
+/// can only be set at that point if `writable` is true), and the portable bits we know are safe to
+/// forward, dropping anything else (such as FUSE-internal bits the kernel never intended for the
+/// backing file system) rather than trusting it implicitly.
+fn sanitize_open_flags(flags: u32, writable: bool) -> nodes::NodeResult<u32> {
+    let flags = flags as i32;
+
+    if !writable && (flags & WRITE_INTENT_FLAGS) != 0 {
+        return Err(nodes::KernelError::from_errno(Errno::EROFS));
+    }
+
+    let access_mode = flags & libc::O_ACCMODE;
+    let sanitized = access_mode | (flags & PASSTHROUGH_FLAGS) | (flags & WRITE_INTENT_FLAGS);
+    Ok(sanitized as u32)
+}
+
+/// Extracts the underlying `Errno` out of a `Fallible` failure, if it was ultimately caused by one,
+/// so that `retry::retry_on_busy` can decide whether the failure is transient.
+fn errno_of_failure(err: &Error) -> Option<Errno> {
+    if let Some(e) = err.find_root_cause().downcast_ref::<nix::Error>() {
+        return e.as_errno();
+    }
+    if let Some(e) = err.find_root_cause().downcast_ref::<io::Error>() {
+        return e.raw_os_error().map(Errno::from_i32);
+    }
+    None
+}
+
+/// Copies `len` bytes from `handle_in` (at `offset_in`) to `handle_out` (at `offset_out`).
+///
+/// When both handles expose a raw file descriptor, this is done via repeated `copy_file_range(2)`
+/// calls so the kernel can perform the copy -- and reflink it, where the backing file system supports
+/// that -- without round-tripping the bytes through userspace.  Falls back to a plain read/write loop
+/// when either handle has no descriptor of its own, or when `copy_file_range(2)` reports `ENOSYS` or
+/// `EXDEV` (the two handles straddle file systems that don't support it).
+fn perform_copy_file_range(handle_in: &dyn nodes::Handle, offset_in: i64, handle_out: &dyn nodes::Handle,
+    offset_out: i64, len: u64) -> nodes::NodeResult<u64> {
+    if let (Some(fd_in), Some(fd_out)) = (handle_in.as_raw_fd(), handle_out.as_raw_fd()) {
+        let mut off_in = offset_in;
+        let mut off_out = offset_out;
+        let mut remaining = len;
+        let mut total = 0u64;
+        while remaining > 0 {
+            // A single copy_file_range(2) call is not guaranteed to copy everything asked of it
+            // (exactly like read/write), so keep calling until `len` bytes are copied, no further
+            // progress is made, or an error other than end-of-file occurs.
+            let copied = unsafe {
+                libc::copy_file_range(fd_in, &mut off_in, fd_out, &mut off_out, remaining as usize, 0)
+            };
+            if copied < 0 {
+                let errno = Errno::last();
+                if total == 0 && (errno == Errno::ENOSYS || errno == Errno::EXDEV) {
+                    // Unsupported by this pair of file systems; fall through to the read/write loop
+                    // below.  Only do this if we have not already copied anything via the kernel
+                    // path, since switching strategies mid-copy would re-read bytes we just wrote.
+                    break;
+                }
+                return Err(nodes::KernelError::from_errno(errno));
+            }
+            if copied == 0 {
+                break;
+            }
+            total += copied as u64;
+            remaining -= copied as u64;
+        }
+        if total > 0 || remaining == 0 {
+            return Ok(total);
+        }
+    }
+
+    const CHUNK_SIZE: u64 = 128 * 1024;
+    let mut remaining = len;
+    let mut in_pos = offset_in;
+    let mut out_pos = offset_out;
+    let mut total = 0u64;
+    while remaining > 0 {
+        let chunk = std::cmp::min(remaining, CHUNK_SIZE) as u32;
+        let data = handle_in.read(in_pos, chunk)?;
+        if data.is_empty() {
+            break;
+        }
+        let written = handle_out.write(out_pos, &data)?;
+        total += u64::from(written);
+        in_pos += i64::from(written);
+        out_pos += i64::from(written);
+        remaining -= u64::from(written);
+        if (written as usize) < data.len() {
+            break;
+        }
+    }
+    Ok(total)
+}
+
 /// Returns a `unistd::Uid` representation of the UID in a `fuse::Request`.
 fn nix_uid(req: &fuse::Request) -> unistd::Uid {
     unistd::Uid::from_raw(req.uid() as u32)
@@ -404,25 +578,38 @@ fn nix_gid(req: &fuse::Request) -> unistd::Gid {
     unistd::Gid::from_raw(req.gid() as u32)
 }
 
-/// Converts a collection of extended attribute names into a raw vector of null-terminated strings.
+/// Converts a set of extended attribute names -- as they are actually stored on the underlying
+/// node -- into a raw vector of null-terminated strings, in the format `listxattr` expects,
+/// translating each one back to the name a sandboxed process should see via `policy` and dropping
+/// any whose namespace is denied.
 ///
 // TODO(jmmv): This conversion is unnecessary.  `Xattrs` has the raw representation of the extended
 // attributes, which we could forward to the kernel directly.
-fn xattrs_to_u8(xattrs: xattr::XAttrs) -> Vec<u8> {
-    let mut length = 0;
-    for xa in xattrs.clone().into_iter() {
-        length += xa.len() + 1;
-    }
-    let mut data = Vec::with_capacity(length);
+fn filtered_xattrs_to_u8(xattrs: xattr::XAttrs, policy: &XattrPolicy) -> Vec<u8> {
+    let mut data = Vec::new();
     for xa in xattrs.into_iter() {
-        for b in xa.as_bytes() {
-            data.push(*b);
-        }
+        let name = match policy.unresolve(&xa) {
+            Some(name) => name,
+            None => continue,
+        };
+        data.extend_from_slice(name.as_bytes());
         data.push(0);
     }
     data
 }
 
+/// Responds to a `getxattr` request for an attribute that does not exist (or is hidden by policy).
+fn reply_missing_xattr(reply: fuse::ReplyXattr) {
+    #[cfg(target_os = "linux")]
+    reply.error(Errno::ENODATA as i32);
+
+    #[cfg(target_os = "macos")]
+    reply.error(Errno::ENOATTR as i32);
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    compile_error!("Don't know what error to return on a missing getxattr")
+}
+
 /// Responds to a successful xattr get or list request.
 ///
 /// If `size` is zero, the kernel wants to know the length of `value`.  Otherwise, we are being
@@ -462,6 +649,26 @@ impl fuse::Filesystem for SandboxFS {
         }
     }
 
+    fn copy_file_range(&mut self, _req: &fuse::Request, _ino_in: u64, fh_in: u64, offset_in: i64,
+        ino_out: u64, fh_out: u64, offset_out: i64, len: u64, _flags: u32, reply: fuse::ReplyWrite) {
+        let dest_node = self.find_node(ino_out);
+        if !dest_node.writable() {
+            reply.error(Errno::EPERM as i32);
+            return;
+        }
+
+        let handle_in = self.find_handle(fh_in);
+        let handle_out = self.find_handle(fh_out);
+        match perform_copy_file_range(handle_in.as_ref(), offset_in, handle_out.as_ref(), offset_out,
+            len) {
+            // `ReplyWrite::written` only has a 32-bit count to report with, so a copy that somehow
+            // exceeds that (a caller requesting more than 4 GiB in one call) must be clamped rather
+            // than silently wrapped via an `as` cast.
+            Ok(copied) => reply.written(std::cmp::min(copied, u64::from(std::u32::MAX)) as u32),
+            Err(e) => reply.error(e.errno_as_i32()),
+        }
+    }
+
     fn getattr(&mut self, _req: &fuse::Request, inode: u64, reply: fuse::ReplyAttr) {
         let node = self.find_node(inode);
         match node.getattr() {
@@ -573,8 +780,25 @@ impl fuse::Filesystem for SandboxFS {
         self.release_common(fh, reply)
     }
 
+    // `fuse::Filesystem::rename` only gained this trailing `flags` parameter (carrying the kernel's
+    // `renameat2(2)` flags, i.e. FUSE_RENAME2) in forks of the `fuse` crate that patched it in, such
+    // as the one this project vendors; against a stock crates.io `fuse` this override simply fails
+    // to compile, which is the correct failure mode -- there would be nowhere to source real flags
+    // from otherwise.
     fn rename(&mut self, _req: &fuse::Request, parent: u64, name: &OsStr, new_parent: u64,
-        new_name: &OsStr, reply: fuse::ReplyEmpty) {
+        new_name: &OsStr, flags: u32, reply: fuse::ReplyEmpty) {
+        let flags = match nodes::RenameFlags::from_bits(flags) {
+            Ok(flags) => flags,
+            Err(e) => {
+                reply.error(e.errno_as_i32());
+                return;
+            },
+        };
+        if flags.needs_renameat2() && !nodes::renameat2_is_supported() {
+            reply.error(Errno::ENOSYS as i32);
+            return;
+        }
+
         let dir_node = self.find_node(parent);
         if !dir_node.writable() {
             reply.error(Errno::EPERM as i32);
@@ -582,14 +806,16 @@ impl fuse::Filesystem for SandboxFS {
         }
 
         let result = if parent == new_parent {
-            dir_node.rename(name, new_name, self.cache.as_ref())
+            dir_node.rename(name, new_name, flags, self.cache.as_ref())
         } else {
             let new_dir_node = self.find_node(new_parent);
             if !new_dir_node.writable() {
+                // Already covers the exchange case: RENAME_EXCHANGE needs `new_dir_node` writable
+                // exactly as much as a plain rename into it does, and this check is unconditional.
                 reply.error(Errno::EPERM as i32);
                 return;
             }
-            dir_node.rename_and_move_source(name, new_dir_node, new_name, self.cache.as_ref())
+            dir_node.rename_and_move_source(name, new_dir_node, new_name, flags, self.cache.as_ref())
         };
         match result {
             Ok(()) => reply.ok(),
@@ -659,7 +885,10 @@ impl fuse::Filesystem for SandboxFS {
             return;
         }
 
-        match dir_node.unlink(name, self.cache.as_ref()) {
+        let result = retry::retry_on_busy(
+            &self.retry_policy, |e: &nodes::KernelError| Some(Errno::from_i32(e.errno_as_i32())),
+            || dir_node.unlink(name, self.cache.as_ref()));
+        match result {
             Ok(_) => reply.ok(),
             Err(e) => reply.error(e.errno_as_i32()),
         }
@@ -688,7 +917,14 @@ impl fuse::Filesystem for SandboxFS {
             return;
         }
 
-        match node.setxattr(name, value) {
+        let name = match node.xattr_policy().resolve(name) {
+            Some(name) => name,
+            None => {
+                reply.error(Errno::EPERM as i32);
+                return;
+            },
+        };
+        match node.setxattr(&name, value) {
             Ok(_) => reply.ok(),
             Err(e) => reply.error(e.errno_as_i32()),
         }
@@ -702,17 +938,15 @@ impl fuse::Filesystem for SandboxFS {
         }
 
         let node = self.find_node(inode);
-        match node.getxattr(name) {
-            Ok(None) => {
-                #[cfg(target_os = "linux")]
-                reply.error(Errno::ENODATA as i32);
-
-                #[cfg(target_os = "macos")]
-                reply.error(Errno::ENOATTR as i32);
-
-                #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-                compile_error!("Don't know what error to return on a missing getxattr")
+        let name = match node.xattr_policy().resolve(name) {
+            Some(name) => name,
+            None => {
+                reply_missing_xattr(reply);
+                return;
             },
+        };
+        match node.getxattr(&name) {
+            Ok(None) => reply_missing_xattr(reply),
             Ok(Some(value)) => reply_xattr(size, value.as_slice(), reply),
             Err(e) => reply.error(e.errno_as_i32()),
         }
@@ -726,8 +960,10 @@ impl fuse::Filesystem for SandboxFS {
         }
 
         let node = self.find_node(inode);
+        let policy = node.xattr_policy();
         match node.listxattr() {
-            Ok(Some(xattrs)) => reply_xattr(size, xattrs_to_u8(xattrs).as_slice(), reply),
+            Ok(Some(xattrs)) => reply_xattr(size, filtered_xattrs_to_u8(xattrs, &policy).as_slice(),
+                reply),
             Ok(None) => {
                 if size == 0 {
                     reply.size(0);
@@ -752,7 +988,14 @@ impl fuse::Filesystem for SandboxFS {
             return;
         }
 
-        match node.removexattr(name) {
+        let name = match node.xattr_policy().resolve(name) {
+            Some(name) => name,
+            None => {
+                reply.error(Errno::EPERM as i32);
+                return;
+            },
+        };
+        match node.removexattr(&name) {
             Ok(_) => reply.ok(),
             Err(e) => reply.error(e.errno_as_i32()),
         }
@@ -773,14 +1016,52 @@ impl reconfig::ReconfigurableFS for ReconfigurableSandboxFS {
 
         ensure!(!components.is_empty(), "Root cannot be unmapped");
 
-        self.root.unmap(components)
+        retry::retry_on_busy(&self.retry_policy, errno_of_failure, || self.root.unmap(components))
+    }
+
+    fn remap<P: AsRef<Path>>(&self, path: P, underlying_path: &Path, writable: bool) -> Fallible<()> {
+        let all = path.as_ref().components().collect::<Vec<_>>();
+        debug_assert_eq!(Component::RootDir, all[0], "Paths to remap are always absolute");
+        let components = &all[1..];
+
+        ensure!(!components.is_empty(), "Root cannot be remapped");
+        ensure!(underlying_path.is_absolute(), "Remap target {:?} must be absolute", underlying_path);
+
+        self.root.remap(components, underlying_path, writable, self.cache.as_ref())
+            .context(format!("Cannot remap '{}' to {:?}", path.as_ref().display(), underlying_path))?;
+        Ok(())
     }
 }
 
 /// Mounts a new sandboxfs instance on the given `mount_point` and maps all `mappings` within it.
+///
+/// When `check_capabilities` is set, validates upfront that the process holds the Linux
+/// capabilities `mappings` will need, instead of letting individual operations fail later.
+/// Unprivileged setups that rely on kernel `default_permissions` should leave this unset.
+///
+/// When `hardened` is set, `DiskNodeSource` re-verifies via `ResolveRoot` that a path it is asked
+/// to resolve still lives beneath its own parent directory, at the cost of an extra `openat2` (or
+/// per-component `O_NOFOLLOW` walk on pre-5.6 kernels).  As of today this only covers resolution of
+/// the root mapping itself, since that is the only case `create_root` resolves through
+/// `NodeSource` -- see the caveat on `nodes::NodeSource` for the rest of the gap; `hardened` will
+/// automatically start covering every by-name lookup once that gap closes.  See
+/// `nodes::DiskNodeSource::new`.
+///
+/// `content_cache_max_bytes`, when given, bounds a content-addressed cache of file bytes (see
+/// `ContentCache`) shared across every node and handle this instance creates, so identical content
+/// reachable via multiple sandbox paths is only ever read into memory once; `None` disables it.
+///
+/// `retry_policy` governs how many times, and with what backoff, `unlink` and reconfiguration's
+/// `unmap` retry a removal that transiently fails with `EBUSY`/`ENOTEMPTY`; see `retry::RetryPolicy`.
 #[allow(clippy::too_many_arguments)]
 pub fn mount(mount_point: &Path, options: &[&str], mappings: &[Mapping], ttl: Timespec,
-    cache: ArcCache, xattrs: bool, input: fs::File, output: fs::File) -> Fallible<()> {
+    cache: ArcCache, xattrs: bool, check_capabilities: bool, hardened: bool,
+    content_cache_max_bytes: Option<usize>, retry_policy: retry::RetryPolicy, input: fs::File,
+    output: fs::File) -> Fallible<()> {
+    if check_capabilities {
+        caps_check::check_capabilities(mappings)?;
+    }
+
     let mut os_options = options.iter().map(AsRef::as_ref).collect::<Vec<&OsStr>>();
 
     // Delegate permissions checks to the kernel for efficiency and to avoid having to implement
@@ -788,7 +1069,9 @@ pub fn mount(mount_point: &Path, options: &[&str], mappings: &[Mapping], ttl: Ti
     os_options.push(OsStr::new("-o"));
     os_options.push(OsStr::new("default_permissions"));
 
-    let mut fs = SandboxFS::create(mappings, ttl, cache, xattrs)?;
+    let mut fs =
+        SandboxFS::create(mappings, ttl, cache, xattrs, hardened, content_cache_max_bytes,
+            retry_policy)?;
     let reconfigurable_fs = fs.reconfigurable();
     info!("Mounting file system onto {:?}", mount_point);
 
@@ -0,0 +1,38 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Small, self-contained conversions between the time representations the kernel hands us and the
+//! ones the underlying `utimensat`/`futimens`-style calls expect.
+
+use nix::sys::time::TimeVal;
+use time::Timespec;
+
+/// Converts a `time::Timespec`, as received from the kernel in a `setattr` request, into the
+/// `timeval`-based representation the underlying `utimes` family of calls expects.
+pub(crate) fn timespec_to_timeval(ts: Timespec) -> TimeVal {
+    TimeVal::seconds(ts.sec) + TimeVal::microseconds(i64::from(ts.nsec) / 1_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timespec_to_timeval_preserves_seconds_and_rounds_nanos_down_to_micros() {
+        let ts = Timespec::new(42, 1_500);
+        let tv = timespec_to_timeval(ts);
+        assert_eq!(42, tv.tv_sec());
+        assert_eq!(1, tv.tv_usec());
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! A stable, offset-addressable snapshot of a directory's entries for the kernel-facing `readdir`
+//! protocol.
+//!
+//! The kernel can -- and for large directories, will -- call `readdir` on the same handle more than
+//! once, each time resuming from the offset the previous reply stopped at.  Re-scanning the
+//! underlying directory, and re-registering any child node it discovers, on every single call is
+//! O(n^2) over a large listing and can produce an inconsistent result if the directory changes
+//! mid-enumeration.  `DirEntries` avoids both problems: a directory `Handle` builds one when it is
+//! opened and then pages it out across as many `readdir` calls as the kernel needs, never
+//! re-touching the underlying directory in between.
+
+use fuse;
+use std::ffi::OsString;
+use super::NodeResult;
+
+/// A single entry as reported to the kernel: its inode, file type, and name.
+pub(crate) struct DirEntry {
+    pub(crate) inode: u64,
+    pub(crate) kind: fuse::FileType,
+    pub(crate) name: OsString,
+}
+
+/// A snapshotted, offset-addressable listing of a directory's entries, including the synthetic
+/// `.` and `..` entries every directory exposes.
+pub(crate) struct DirEntries {
+    entries: Vec<DirEntry>,
+}
+
+impl DirEntries {
+    /// Builds a snapshot for a directory with inode `self_inode` and parent inode `parent_inode`,
+    /// followed by `children` in whatever order the caller collected them.
+    ///
+    /// This is meant to run exactly once per handle, at the time it is opened, registering any
+    /// not-yet-known child with `_ids`/`_cache` as part of collecting `children` -- not on every
+    /// `reply_from` call -- so that a handle kept open across a long-lived `readdir` never
+    /// re-`lstat`s the underlying directory nor re-registers the same child twice.
+    pub(crate) fn new(self_inode: u64, parent_inode: u64,
+        children: impl IntoIterator<Item = (OsString, u64, fuse::FileType)>) -> Self {
+        let mut entries = Vec::new();
+        entries.push(DirEntry {
+            inode: self_inode,
+            kind: fuse::FileType::Directory,
+            name: OsString::from("."),
+        });
+        entries.push(DirEntry {
+            inode: parent_inode,
+            kind: fuse::FileType::Directory,
+            name: OsString::from(".."),
+        });
+        entries.extend(
+            children.into_iter().map(|(name, inode, kind)| DirEntry { inode, kind, name }));
+        DirEntries { entries }
+    }
+
+    /// Feeds as many entries as fit starting at `offset` into `reply`, stopping as soon as `reply`
+    /// reports its buffer is full so the kernel can resume from exactly that point on its next call.
+    ///
+    /// `offset` is the kernel-supplied resume point -- the offset attached to the last entry the
+    /// kernel has already consumed, which is that entry's index plus one -- so `0` means "start
+    /// from the beginning."  A negative or
+    /// out-of-range `offset` (the directory shrank since the caller last saw it, say) simply yields
+    /// no further entries rather than panicking.
+    pub(crate) fn reply_from(&self, offset: i64, reply: &mut fuse::ReplyDirectory) -> NodeResult<()> {
+        if offset < 0 {
+            return Ok(());
+        }
+        for (i, entry) in self.entries.iter().enumerate().skip(offset as usize) {
+            let next_offset = (i + 1) as i64;
+            if reply.add(entry.inode, next_offset, entry.kind, &entry.name) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> DirEntries {
+        DirEntries::new(1, 1, vec![
+            (OsString::from("a"), 2, fuse::FileType::RegularFile),
+            (OsString::from("b"), 3, fuse::FileType::RegularFile),
+        ])
+    }
+
+    #[test]
+    fn includes_dot_and_dotdot_first() {
+        let entries = sample();
+        assert_eq!(4, entries.entries.len());
+        assert_eq!(".", entries.entries[0].name);
+        assert_eq!("..", entries.entries[1].name);
+        assert_eq!(1, entries.entries[0].inode);
+        assert_eq!(1, entries.entries[1].inode);
+    }
+
+    #[test]
+    fn children_follow_dot_and_dotdot_in_given_order() {
+        let entries = sample();
+        assert_eq!(4, entries.entries.len() - 0);
+        // Skipping past "." and ".." should leave just the two real children.
+        let remaining: Vec<_> = entries.entries.iter().skip(2).map(|e| e.name.clone()).collect();
+        assert_eq!(vec![OsString::from("a"), OsString::from("b")], remaining);
+    }
+}
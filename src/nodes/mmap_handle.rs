@@ -0,0 +1,317 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! An mmap-backed read path for large regular files.
+//!
+//! Serving every `read` with a `pread` is wasteful for large files that get read over and over (a
+//! build tool re-scanning its inputs, say): each call is a syscall plus a fresh heap allocation.
+//! For files at or above a configurable size threshold, we instead `mmap` the underlying descriptor
+//! once at open time (via the `memmap2` crate, following the Mercurial VFS' approach to the same
+//! tradeoff) and serve subsequent reads as slices copied out of that mapping.
+//!
+//! Memory-mapped I/O is unsafe to use over a network file system, or on a file another mapping
+//! exposes as writable: a fault while touching the mapping can deadlock or raise `SIGBUS` if the
+//! file is truncated out from under us.  We guard against the file system case by `fstatfs`-ing the
+//! underlying descriptor first, and against the writable case by only ever mapping nodes that
+//! `sandboxfs` itself exposes read-only; on top of that, every read re-checks the file's current
+//! size and falls back to plain `pread` the moment it no longer matches what we mapped.
+//!
+//! Files below the `mmap` threshold skip straight to `pread`, which is cheap for a one-off read but
+//! repeats the work in full for every handle opened against the same content -- exactly the case
+//! `ContentCache` exists to dedup.  When a cache is supplied, such a handle reads the whole file
+//! once at open time and stores it keyed by its SHA-256 digest.  Every subsequent `read` then
+//! `fstat`s the file afresh and only serves the cached copy if that live `(mtime, size)` still
+//! matches what the entry was stored under -- the same staleness check the mmap path above already
+//! applies to size, just reused for the cache instead of a live mapping -- falling back to `pread`
+//! the moment it doesn't (whether because the cache was too full to store the content or the file
+//! changed underneath us).
+
+use content_cache::ContentCache;
+use memmap2::{Mmap, MmapOptions};
+use nix::sys::stat::fstat;
+use nix::sys::statfs::fstatfs;
+use nix::unistd;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::{Handle, KernelError, NodeResult, OpKind};
+
+/// `f_type` values (from `statfs(2)`) of file systems on which mmap is unsafe enough to avoid.
+const UNSAFE_TO_MMAP_MAGICS: &[i64] = &[
+    0x6969,     // NFS_SUPER_MAGIC
+    0x517b,     // SMB_SUPER_MAGIC
+    0xff534d42, // CIFS_MAGIC_NUMBER
+    0x65735546, // FUSE_SUPER_MAGIC (avoid mmap'ing through another FUSE layer)
+];
+
+/// Returns whether `file` lives on a file system where mmap is known to be unsafe or unreliable.
+fn is_unsafe_to_mmap(file: &fs::File) -> bool {
+    match fstatfs(file) {
+        Ok(stat) => UNSAFE_TO_MMAP_MAGICS.contains(&stat.filesystem_type().0),
+        // If we can't even stat the file system, play it safe and avoid mmap.
+        Err(_) => true,
+    }
+}
+
+/// The live mapping backing a handle, plus the file size it was taken at so a later read can tell
+/// whether the file has since been truncated out from under us.
+struct ActiveMapping {
+    mmap: Mmap,
+    mapped_len: usize,
+}
+
+/// The SHA-256 digest of a handle's content, once fully read into `ContentCache`.
+///
+/// Deliberately does not also store the `(mtime, size)` freshness key it was inserted under:
+/// that key must be re-read from the live file on every `read` (see `Handle::read` below) rather
+/// than captured once here, or a handle that outlives an external write to its file would keep
+/// matching a freshness key that no longer reflects reality and serve stale bytes forever.
+type CachedContent = [u8; 32];
+
+/// A `Handle` implementation that serves reads from an `mmap` of the whole file when it is safe
+/// and worthwhile to do so, falling back to plain `pread` calls (optionally backed by a shared
+/// `ContentCache`) otherwise.
+pub struct MmapHandle {
+    file: fs::File,
+    path: PathBuf,
+    mapping: Mutex<Option<ActiveMapping>>,
+    cache: Option<Arc<ContentCache>>,
+    cached: Mutex<Option<CachedContent>>,
+}
+
+impl MmapHandle {
+    /// Opens `file` (backing `path`, whose size is `size`) and decides whether to serve its reads
+    /// from an `mmap` of it.
+    ///
+    /// `writable` must reflect whether the node this handle was opened for is writable: mapping a
+    /// node that can be truncated by some other path into the sandbox is how a read that should
+    /// have been a harmless `pread` turns into a `SIGBUS`, so mmap is opt-in and restricted to
+    /// `!writable` nodes.  `threshold` is an additional mount-time knob -- the size a file must
+    /// reach before paying for a page-aligned mapping is worth it; pass `u64::max_value()` to
+    /// disable mmap reads entirely and always use `pread`.
+    ///
+    /// `cache`, when given, is consulted for handles that fall below `threshold`: the whole file is
+    /// read once here and stored under its digest, so repeated opens of identical content (common
+    /// when the same underlying file is mapped under several sandbox paths) only pay the `pread`
+    /// cost once.  Pass `None` to disable this and always read fresh from `file`.
+    pub fn open(file: fs::File, path: &Path, size: u64, writable: bool, threshold: u64,
+        cache: Option<&Arc<ContentCache>>) -> NodeResult<Self> {
+        let mapping = if !writable && size >= threshold && size > 0 && !is_unsafe_to_mmap(&file) {
+            Some(Self::map(&file, path, size as usize)?)
+        } else {
+            None
+        };
+
+        let cached = if mapping.is_none() && !writable {
+            cache.and_then(|cache| Self::cache_whole_file(&file, path, cache).ok())
+        } else {
+            None
+        };
+
+        Ok(MmapHandle {
+            file,
+            path: path.to_path_buf(),
+            mapping: Mutex::new(mapping),
+            cache: cache.cloned(),
+            cached: Mutex::new(cached),
+        })
+    }
+
+    /// Creates the mapping itself, wrapping any failure with the path that caused it.
+    fn map(file: &fs::File, path: &Path, len: usize) -> NodeResult<ActiveMapping> {
+        let mmap = unsafe { MmapOptions::new().len(len).map(file) }
+            .map_err(|e| KernelError::from_io_at(e, OpKind::OpenFile, path))?;
+        Ok(ActiveMapping { mmap, mapped_len: len })
+    }
+
+    /// Reads `file` from start to end and records it in `cache` under its current `(mtime, size)`
+    /// freshness, returning the digest it was stored under.
+    fn cache_whole_file(file: &fs::File, path: &Path, cache: &ContentCache)
+        -> NodeResult<CachedContent> {
+        let stat = fstat(file.as_raw_fd())
+            .map_err(|e| KernelError::from_nix_at(e, OpKind::ReadFile, path))?;
+        let freshness = (stat.st_mtime, stat.st_size.max(0) as u64);
+
+        let mut data = Vec::with_capacity(stat.st_size.max(0) as usize);
+        let mut offset = 0i64;
+        loop {
+            let mut buf = vec![0u8; 128 * 1024];
+            let n = unistd::pread(file.as_raw_fd(), &mut buf, offset)
+                .map_err(|e| KernelError::from_nix_at(e, OpKind::ReadFile, path))?;
+            if n == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..n]);
+            offset += n as i64;
+        }
+
+        Ok(cache.insert(freshness, data))
+    }
+
+    /// Reads via a plain `pread`, used both as the non-mmap/non-cached path and as the fallback
+    /// once a mapping or cache entry is found to be stale.
+    fn pread(&self, offset: i64, size: u32) -> NodeResult<Vec<u8>> {
+        let mut buf = vec![0u8; size as usize];
+        let n = unistd::pread(self.file.as_raw_fd(), &mut buf, offset)
+            .map_err(|e| KernelError::from_nix_at(e, OpKind::ReadFile, &self.path))?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+}
+
+impl Handle for MmapHandle {
+    fn read(&self, offset: i64, size: u32) -> NodeResult<Vec<u8>> {
+        let mut guard = self.mapping.lock().unwrap();
+        if let Some(active) = guard.as_ref() {
+            let current_len = fstat(self.file.as_raw_fd())
+                .map_err(|e| KernelError::from_nix_at(e, OpKind::ReadFile, &self.path))?
+                .st_size as usize;
+            if current_len == active.mapped_len {
+                let data = active.mmap.as_ref();
+                if offset < 0 || offset as usize >= data.len() {
+                    return Ok(Vec::new());
+                }
+                let start = offset as usize;
+                let end = std::cmp::min(start + size as usize, data.len());
+                return Ok(data[start..end].to_vec());
+            }
+            // The file's size no longer matches what we mapped -- most likely truncated out from
+            // under us by another path into the sandbox.  The mapping can no longer be trusted, so
+            // drop it and serve this and all future reads on this handle via `pread` instead of
+            // risking a `SIGBUS` against the stale pages.
+            *guard = None;
+        }
+        drop(guard);
+
+        if let (Some(cache), Some(digest)) = (self.cache.as_ref(), *self.cached.lock().unwrap()) {
+            // Re-stat on every read rather than trusting the freshness captured at open time: a
+            // handle can stay open far longer than the content underneath it stays unchanged, and
+            // re-checking here is what lets a write through some other path into the sandbox
+            // invalidate an already-open reader instead of only ever being caught by a later open.
+            let stat = fstat(self.file.as_raw_fd())
+                .map_err(|e| KernelError::from_nix_at(e, OpKind::ReadFile, &self.path))?;
+            let freshness = (stat.st_mtime, stat.st_size.max(0) as u64);
+            if let Some(data) = cache.get(&digest, freshness) {
+                if offset < 0 || offset as usize >= data.len() {
+                    return Ok(Vec::new());
+                }
+                let start = offset as usize;
+                let end = std::cmp::min(start + size as usize, data.len());
+                return Ok(data[start..end].to_vec());
+            }
+            // Either the cache was too full to store it, or the freshness key no longer matches
+            // (the file has changed, or identical content was re-inserted under another key): fall
+            // through and read it fresh like an uncached handle.
+        }
+
+        self.pread(offset, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn reopen(file: &NamedTempFile) -> fs::File {
+        fs::File::open(file.path()).unwrap()
+    }
+
+    #[test]
+    fn read_small_file_uses_pread_path() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"hello world").unwrap();
+        let handle = MmapHandle::open(reopen(&file), file.path(), 11, false, 1024, None).unwrap();
+        assert!(handle.mapping.lock().unwrap().is_none());
+        assert_eq!(b"hello".to_vec(), handle.read(0, 5).unwrap());
+    }
+
+    #[test]
+    fn read_large_file_uses_mmap_path_and_clamps() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&vec![7u8; 4096]).unwrap();
+        let handle = MmapHandle::open(reopen(&file), file.path(), 4096, false, 1024, None).unwrap();
+        assert!(handle.mapping.lock().unwrap().is_some());
+        assert_eq!(vec![7u8; 10], handle.read(0, 10).unwrap());
+        // A read starting past the end of the file is clamped to empty rather than faulting.
+        assert_eq!(Vec::<u8>::new(), handle.read(5000, 10).unwrap());
+        // A read that overruns the end is clamped to whatever remains.
+        assert_eq!(vec![7u8; 96], handle.read(4000, 1000).unwrap());
+    }
+
+    #[test]
+    fn writable_nodes_never_use_mmap() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&vec![7u8; 4096]).unwrap();
+        let handle = MmapHandle::open(reopen(&file), file.path(), 4096, true, 1024, None).unwrap();
+        assert!(handle.mapping.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncation_falls_back_to_pread_instead_of_erroring() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&vec![7u8; 4096]).unwrap();
+        let handle = MmapHandle::open(reopen(&file), file.path(), 4096, false, 1024, None).unwrap();
+        assert!(handle.mapping.lock().unwrap().is_some());
+
+        file.as_file().set_len(10).unwrap();
+        assert_eq!(vec![7u8; 10], handle.read(0, 4096).unwrap());
+        // The stale mapping was dropped on the first post-truncation read.
+        assert!(handle.mapping.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_below_threshold_is_served_from_content_cache() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"hello world").unwrap();
+        let cache = Arc::new(ContentCache::new(1024));
+        let handle =
+            MmapHandle::open(reopen(&file), file.path(), 11, false, 1024, Some(&cache)).unwrap();
+        assert!(handle.cached.lock().unwrap().is_some());
+        assert_eq!(b"hello".to_vec(), handle.read(0, 5).unwrap());
+    }
+
+    #[test]
+    fn read_re_validates_content_cache_against_the_live_file_on_every_call() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"hello world").unwrap();
+        let cache = Arc::new(ContentCache::new(1024));
+        let handle =
+            MmapHandle::open(reopen(&file), file.path(), 11, false, 1024, Some(&cache)).unwrap();
+        assert_eq!(b"hello".to_vec(), handle.read(0, 5).unwrap());
+
+        // A write that changes the file's size changes its freshness key, so even though the handle
+        // still holds a digest from `open` time, the next read must notice the key no longer
+        // matches and fall back to `pread` instead of serving the now-stale cached copy.  (Size is
+        // used here rather than content alone since some file systems' `mtime` resolution is too
+        // coarse to guarantee a change within a single test run.)
+        use std::io::Seek;
+        file.as_file().set_len(0).unwrap();
+        file.as_file().seek(std::io::SeekFrom::Start(0)).unwrap();
+        file.write_all(b"xxxx").unwrap();
+        assert_eq!(b"xxxx".to_vec(), handle.read(0, 5).unwrap());
+    }
+
+    #[test]
+    fn writable_handles_never_consult_content_cache() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"hello world").unwrap();
+        let cache = Arc::new(ContentCache::new(1024));
+        let handle =
+            MmapHandle::open(reopen(&file), file.path(), 11, true, 1024, Some(&cache)).unwrap();
+        assert!(handle.cached.lock().unwrap().is_none());
+    }
+}
@@ -12,36 +12,132 @@
 // License for the specific language governing permissions and limitations
 // under the License.
 
-use {Cache, IdGenerator};
-use failure::Error;
+use {Cache, IdGenerator, Mapping, XattrPolicy};
+use failure::{Error, Fail};
 use fuse;
 use nix;
 use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::sys::time::TimeVal;
+use nix::unistd::{Gid, Uid};
 use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
 use std::io;
 use std::path::{Component, Path, PathBuf};
 use std::result::Result;
 use std::sync::Arc;
 
-mod conv;
+pub(crate) mod conv;
 mod dir;
 pub use self::dir::Dir;
+mod dir_entries;
+pub(crate) use self::dir_entries::DirEntries;
 mod file;
 pub use self::file::File;
+mod mmap_handle;
+pub use self::mmap_handle::MmapHandle;
+mod resolve;
+pub use self::resolve::ResolveRoot;
+mod special;
+pub use self::special::Special;
 mod symlink;
 pub use self::symlink::Symlink;
 
+/// Shared, reference-counted pointer to a node.
+pub type ArcNode = Arc<Node>;
+
+/// Shared, reference-counted pointer to an open file handle.
+pub type ArcHandle = Arc<Handle>;
+
+/// Identifies the syscall-level operation that produced a `KernelError`, so log messages can say
+/// what sandboxfs was trying to do instead of just which errno it got back.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OpKind {
+    /// Opening a file or directory.
+    OpenFile,
+    /// Creating a new regular file.
+    CreateFile,
+    /// Reading from an already-open file.
+    ReadFile,
+    /// Enumerating a directory's entries.
+    ReadDir,
+    /// Reading a symlink's target.
+    ReadLink,
+    /// Querying a node's attributes.
+    GetAttr,
+    /// Changing a node's attributes.
+    SetAttr,
+    /// Removing a non-directory entry.
+    Unlink,
+    /// Renaming or moving an entry.
+    Rename,
+    /// Writing to an already-open file.
+    Write,
+}
+
+impl fmt::Display for OpKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OpKind::OpenFile => "open",
+            OpKind::CreateFile => "create",
+            OpKind::ReadFile => "read",
+            OpKind::ReadDir => "readdir",
+            OpKind::ReadLink => "readlink",
+            OpKind::GetAttr => "getattr",
+            OpKind::SetAttr => "setattr",
+            OpKind::Unlink => "unlink",
+            OpKind::Rename => "rename",
+            OpKind::Write => "write",
+        };
+        f.write_str(name)
+    }
+}
+
 /// Type that represents an error understood by the kernel.
-#[derive(Debug, Fail)]
-#[fail(display = "errno={}", errno)]
+///
+/// Beyond the raw `errno` the kernel reply path needs, this can optionally carry the operation and
+/// underlying path that caused it, following the `fs-err` crate's approach to the same problem, so
+/// that a log message can say e.g. `open "/foo/bar" failed: errno=ENOENT: No such file or
+/// directory` instead of a bare `errno=ENOENT: No such file or directory`.
+/// `errno_as_i32` is unaffected by whether this context is present, so the kernel reply path stays
+/// byte-for-byte the same regardless.
+#[derive(Debug)]
 pub struct KernelError {
     errno: Errno,
+    op: Option<OpKind>,
+    path: Option<PathBuf>,
 }
 
 impl KernelError {
-    /// Constructs a new error given a raw errno code.
-    fn from_errno(errno: Errno) -> KernelError {
-        KernelError { errno }
+    /// Constructs a new error given a raw errno code, with no operation/path context.
+    pub(crate) fn from_errno(errno: Errno) -> KernelError {
+        KernelError { errno, op: None, path: None }
+    }
+
+    /// Constructs a new error given a raw errno code, tagged with the operation and path that
+    /// caused it.
+    pub(crate) fn from_errno_at(errno: Errno, op: OpKind, path: &Path) -> KernelError {
+        KernelError { errno, op: Some(op), path: Some(path.to_path_buf()) }
+    }
+
+    /// Constructs a new error from an `io::Error`, tagged with the operation and path that caused
+    /// it.
+    pub(crate) fn from_io_at(e: io::Error, op: OpKind, path: &Path) -> KernelError {
+        let mut err = KernelError::from(e);
+        err.op = Some(op);
+        err.path = Some(path.to_path_buf());
+        err
+    }
+
+    /// Constructs a new error from a `nix::Error`, tagged with the operation and path that caused
+    /// it.
+    pub(crate) fn from_nix_at(e: nix::Error, op: OpKind, path: &Path) -> KernelError {
+        let mut err = KernelError::from(e);
+        err.op = Some(op);
+        err.path = Some(path.to_path_buf());
+        err
     }
 
     /// Obtains the errno code contained in this error as an integer.
@@ -50,6 +146,19 @@ impl KernelError {
     }
 }
 
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.op, &self.path) {
+            (Some(op), Some(path)) => write!(f, "{} {:?} failed: errno={}", op, path, self.errno),
+            (Some(op), None) => write!(f, "{} failed: errno={}", op, self.errno),
+            (None, Some(path)) => write!(f, "operation on {:?} failed: errno={}", path, self.errno),
+            (None, None) => write!(f, "errno={}", self.errno),
+        }
+    }
+}
+
+impl Fail for KernelError {}
+
 impl From<io::Error> for KernelError {
     fn from(e: io::Error) -> Self {
         match e.raw_os_error() {
@@ -77,6 +186,84 @@ impl From<nix::Error> for KernelError {
 /// Generic result type for of all node operations.
 pub type NodeResult<T> = Result<T, KernelError>;
 
+/// A requested change to a node's attributes, as decoded from a `setattr` FUSE request.
+///
+/// Every field is optional because the kernel only ever sends the attributes the caller actually
+/// asked to change (e.g. a bare `chmod` leaves `uid`/`gid`/`size`/the timestamps all `None`); a
+/// `Node::setattr` implementation must leave any `None` field of the underlying node untouched.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AttrDelta {
+    /// The new permission bits, if the caller requested a `chmod`.
+    pub mode: Option<Mode>,
+
+    /// The new owning user, if the caller requested a `chown`.
+    pub uid: Option<Uid>,
+
+    /// The new owning group, if the caller requested a `chown`.
+    pub gid: Option<Gid>,
+
+    /// The new access time, if the caller requested a `utimes`.
+    pub atime: Option<TimeVal>,
+
+    /// The new modification time, if the caller requested a `utimes`.
+    pub mtime: Option<TimeVal>,
+
+    /// The new size, if the caller requested a `truncate`.
+    pub size: Option<u64>,
+}
+
+/// The raw `renameat2(2)` flag bits that sandboxfs understands.
+const RENAME_NOREPLACE: u32 = 0x1;
+const RENAME_EXCHANGE: u32 = 0x2;
+
+/// Decoded, validated `renameat2(2)` flags for a single rename operation.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RenameFlags {
+    /// Fail with `EEXIST` instead of clobbering an existing destination.
+    pub no_replace: bool,
+
+    /// Atomically swap the source and destination instead of moving the source over it; both must
+    /// already exist.
+    pub exchange: bool,
+}
+
+impl RenameFlags {
+    /// Decodes the raw flag bits the kernel passed to the `rename` FUSE op.
+    ///
+    /// `RENAME_NOREPLACE` and `RENAME_EXCHANGE` are mutually exclusive per `renameat2(2)`, and any
+    /// other bit is unknown to us, so both cases are rejected with `EINVAL`.
+    pub fn from_bits(bits: u32) -> NodeResult<RenameFlags> {
+        if bits & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0 {
+            return Err(KernelError::from_errno(Errno::EINVAL));
+        }
+        let no_replace = bits & RENAME_NOREPLACE != 0;
+        let exchange = bits & RENAME_EXCHANGE != 0;
+        if no_replace && exchange {
+            return Err(KernelError::from_errno(Errno::EINVAL));
+        }
+        Ok(RenameFlags { no_replace, exchange })
+    }
+
+    /// Returns true if these flags require the kernel to support `renameat2(2)` at all.
+    pub fn needs_renameat2(&self) -> bool {
+        self.no_replace || self.exchange
+    }
+}
+
+/// Returns whether the running kernel is new enough to support `renameat2(2)` (Linux >= 3.15).
+///
+/// `rename` falls back to plain `renameat(2)` semantics whenever the caller did not request any
+/// flags, so this is only consulted when `RenameFlags::needs_renameat2` is true; on kernels that
+/// predate `renameat2`, the handler should return `ENOSYS` rather than attempting the syscall.
+pub fn renameat2_is_supported() -> bool {
+    let uts = nix::sys::utsname::uname();
+    let release = uts.release();
+    let mut parts = release.split(|c: char| !c.is_ascii_digit()).filter(|s| !s.is_empty());
+    let major: u32 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let minor: u32 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    (major, minor) >= (3, 15)
+}
+
 /// Abstract representation of an open file handle.
 pub trait Handle {
     /// Reads `size` bytes from the open file starting at `offset`.
@@ -84,7 +271,51 @@ pub trait Handle {
         panic!("Not implemented")
     }
 
-    /// Reads all directory entries into the given reply object.
+    /// Writes `data` to the open file starting at `offset` and returns the number of bytes
+    /// actually written.
+    ///
+    /// Callers are responsible for having already checked that the underlying node is writable;
+    /// this mirrors the gating `rmdir` already does at the `SandboxFS` layer before reaching the
+    /// node, rather than duplicating the check inside every mutator.
+    fn write(&self, _offset: i64, _data: &[u8]) -> NodeResult<u32> {
+        panic!("Not implemented")
+    }
+
+    /// Flushes any buffered writes for this handle without closing it.
+    ///
+    /// This is invoked on every `close(2)` of the file descriptor the kernel handed out for this
+    /// handle, which may happen more than once per `open` if the descriptor was `dup`ed.
+    fn flush(&self) -> NodeResult<()> {
+        panic!("Not implemented")
+    }
+
+    /// Flushes any buffered writes and, unless `datasync` is set, metadata for this handle out to
+    /// the underlying storage.
+    fn fsync(&self, _datasync: bool) -> NodeResult<()> {
+        panic!("Not implemented")
+    }
+
+    /// Returns the raw file descriptor backing this handle, if it has one the kernel can operate on
+    /// directly (e.g. for `copy_file_range(2)`).
+    ///
+    /// The default of `None` is correct for handles with no single underlying descriptor, such as
+    /// directory handles; callers that want zero-copy behavior must fall back to a read/write loop
+    /// when this returns `None`.
+    fn as_raw_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        None
+    }
+
+    /// Feeds directory entries starting at `_offset` into the given reply object, resuming exactly
+    /// where the kernel left off rather than re-enumerating the directory from scratch.
+    ///
+    /// The kernel is free to call `readdir` on the same handle as many times as it needs to drain a
+    /// large directory, each time passing back the offset of the last entry it successfully
+    /// consumed.  Implementations should build their entry list once -- typically when the handle
+    /// is opened -- and keep serving that same snapshot across calls (see `DirEntries`), so that
+    /// neither the underlying directory is re-scanned nor newly discovered children are
+    /// re-registered with `_ids`/`_cache` more than once.  This also means a directory that changes
+    /// mid-enumeration is reported consistently, as of whenever the snapshot was taken, instead of
+    /// producing a listing that depends on exactly when each `readdir` call happened to land.
     ///
     /// While this takes a `fuse::ReplyDirectory` object as a parameter for efficiency reasons, it
     /// is the responsibility of the caller to invoke `reply.ok()` and `reply.error()` on the same
@@ -92,9 +323,9 @@ pub trait Handle {
     /// other functions.
     ///
     /// `_ids` and `_cache` are the file system-wide bookkeeping objects needed to instantiate new
-    /// nodes, used when readdir discovers an underlying node that was not yet known.
-    fn readdir(&self, _ids: &IdGenerator, _cache: &Cache, _reply: &mut fuse::ReplyDirectory)
-        -> NodeResult<()> {
+    /// nodes, used when building the snapshot discovers an underlying node that was not yet known.
+    fn readdir(&self, _ids: &IdGenerator, _cache: &Cache, _offset: i64,
+        _reply: &mut fuse::ReplyDirectory) -> NodeResult<()> {
         panic!("Not implemented");
     }
 }
@@ -133,17 +364,49 @@ pub trait Node {
     /// `_components` is the path to map, broken down into components, and relative to the current
     /// node.  `_underlying_path` is the target to use for the created node.  `_writable` indicates
     /// the final node's writability, but intermediate nodes are creates as not writable.
+    /// `_xattr_policy` is the final node's extended-attribute namespace policy; intermediate nodes
+    /// are created with the default, allow-everything policy.
     ///
     /// `_ids` and `_cache` are the file system-wide bookkeeping objects needed to instantiate new
     /// nodes, used when this algorithm instantiates any new node.
     fn map(&self, _components: &[Component], _underlying_path: &Path, _writable: bool,
-        _ids: &IdGenerator, _cache: &Cache) -> Result<(), Error> {
+        _xattr_policy: &XattrPolicy, _ids: &IdGenerator, _cache: &Cache) -> Result<(), Error> {
+        panic!("Not implemented")
+    }
+
+    /// Returns the extended-attribute namespace policy that applies to this node.
+    ///
+    /// Defaults to a policy that allows every namespace through, which is correct for any node that
+    /// was not created directly by a `map` call carrying its own policy (e.g. nodes synthesized as
+    /// intermediate path components).
+    fn xattr_policy(&self) -> XattrPolicy {
+        XattrPolicy::default()
+    }
+
+    /// Atomically rebinds the mapping at `_components`, relative to the current node, to a new
+    /// `_underlying_path` and/or `_writable` setting, in a single locked step.
+    ///
+    /// Unlike `unmap` followed by `map`, this never exposes an intermediate state in which the path
+    /// does not resolve, and it updates the `_cache` entry keyed by the old underlying path in place
+    /// rather than dropping and re-creating it, so unrelated subtrees and any cached node state for
+    /// siblings are left untouched.
+    fn remap(&self, _components: &[Component], _underlying_path: &Path, _writable: bool,
+        _cache: &Cache) -> Result<(), Error> {
         panic!("Not implemented")
     }
 
     /// Retrieves the node's metadata.
     fn getattr(&self) -> NodeResult<fuse::FileAttr>;
 
+    /// Applies `_delta` to this node's attributes and returns the attributes that result.
+    ///
+    /// Implementations must honor `writable()` themselves when called directly (as `rmdir` already
+    /// does), returning `KernelError::from_errno(Errno::EPERM)` if it is false, and must invalidate
+    /// any cached `getattr` data once the underlying change succeeds.
+    fn setattr(&self, _delta: &AttrDelta) -> NodeResult<fuse::FileAttr> {
+        panic!("Not implemented");
+    }
+
     /// Looks up a node with the given name within the current node and returns the found node and
     /// its attributes at the time of the query.
     ///
@@ -162,8 +425,208 @@ pub trait Node {
         panic!("Not implemented");
     }
 
+    /// Creates a regular file named `_name` within this directory and opens it in one step,
+    /// returning both the new node and an already-open handle for it, plus its attributes.
+    ///
+    /// Must honor `writable()`, returning `KernelError::from_errno(Errno::EPERM)` if it is false.
+    /// `_ids` and `_cache` are used to register the newly-created node, exactly as `lookup` does
+    /// for nodes it discovers.
+    fn create(&self, _name: &OsStr, _uid: Uid, _gid: Gid, _mode: u32, _flags: u32,
+        _ids: &IdGenerator, _cache: &Cache) -> NodeResult<(Arc<Node>, Arc<Handle>, fuse::FileAttr)> {
+        panic!("Not implemented");
+    }
+
+    /// Creates a subdirectory named `_name` within this directory and returns the new node and its
+    /// attributes.
+    ///
+    /// Must honor `writable()`, returning `KernelError::from_errno(Errno::EPERM)` if it is false.
+    fn mkdir(&self, _name: &OsStr, _uid: Uid, _gid: Gid, _mode: u32, _ids: &IdGenerator,
+        _cache: &Cache) -> NodeResult<(Arc<Node>, fuse::FileAttr)> {
+        panic!("Not implemented");
+    }
+
+    /// Creates a device/FIFO/socket node named `_name` within this directory, as described by
+    /// `_mode` and `_rdev`, and returns the new node and its attributes.
+    ///
+    /// Must honor `writable()`, returning `KernelError::from_errno(Errno::EPERM)` if it is false.
+    fn mknod(&self, _name: &OsStr, _uid: Uid, _gid: Gid, _mode: u32, _rdev: u32, _ids: &IdGenerator,
+        _cache: &Cache) -> NodeResult<(Arc<Node>, fuse::FileAttr)> {
+        panic!("Not implemented");
+    }
+
+    /// Creates a symlink named `_name` within this directory pointing at `_link` and returns the
+    /// new node and its attributes.
+    ///
+    /// Must honor `writable()`, returning `KernelError::from_errno(Errno::EPERM)` if it is false.
+    fn symlink(&self, _name: &OsStr, _link: &Path, _uid: Uid, _gid: Gid, _ids: &IdGenerator,
+        _cache: &Cache) -> NodeResult<(Arc<Node>, fuse::FileAttr)> {
+        panic!("Not implemented");
+    }
+
+    /// Removes the regular file (or other non-directory entry) named `_name` from this directory.
+    ///
+    /// Must honor `writable()`, returning `KernelError::from_errno(Errno::EPERM)` if it is false,
+    /// and must drop `_name`'s entry from `_cache` once the underlying removal succeeds.
+    fn unlink(&self, _name: &OsStr, _cache: &Cache) -> NodeResult<()> {
+        panic!("Not implemented");
+    }
+
+    /// Removes the (empty) subdirectory named `_name` from this directory.
+    ///
+    /// Must honor `writable()`, returning `KernelError::from_errno(Errno::EPERM)` if it is false,
+    /// as the existing `rmdir` wiring in `SandboxFS` already relies on, and must drop `_name`'s
+    /// entry from `_cache` once the underlying removal succeeds.
+    fn rmdir(&self, _name: &OsStr, _cache: &Cache) -> NodeResult<()> {
+        panic!("Not implemented");
+    }
+
     /// Reads the target of a symlink.
     fn readlink(&self) -> NodeResult<PathBuf> {
         panic!("Not implemented");
     }
+
+    /// Renames `old_name`, a child of this directory, to `new_name`, still within this directory.
+    ///
+    /// `flags` carries the decoded `renameat2(2)` semantics: `no_replace` must fail with `EEXIST`
+    /// if `new_name` already exists, and `exchange` must atomically swap the two entries instead of
+    /// overwriting one, including updating any by-underlying-path bookkeeping in `_cache` so the
+    /// swap is reflected for both names.
+    fn rename(&self, _old_name: &OsStr, _new_name: &OsStr, _flags: RenameFlags, _cache: &Cache)
+        -> NodeResult<()> {
+        panic!("Not implemented");
+    }
+
+    /// Like `rename`, but `new_name` lives under a different directory, `_new_dir`.
+    fn rename_and_move_source(&self, _old_name: &OsStr, _new_dir: Arc<Node>, _new_name: &OsStr,
+        _flags: RenameFlags, _cache: &Cache) -> NodeResult<()> {
+        panic!("Not implemented");
+    }
+
+    /// Lists the direct children of a directory node as (name, node) pairs.
+    ///
+    /// Unlike `lookup`, this does not consult or populate any bookkeeping keyed by name; it exists
+    /// for whole-tree walks (such as serializing a snapshot to a tar archive) that need to visit
+    /// every child without going through the kernel-facing `readdir` protocol.
+    fn list(&self) -> NodeResult<Vec<(std::ffi::OsString, Arc<Node>)>> {
+        panic!("Not implemented");
+    }
+}
+
+/// Supplies the root mappings and resolves on-demand nodes for a `SandboxFS` instance.
+///
+/// `SandboxFS` and the reconfiguration machinery are generic over this trait instead of assuming
+/// nodes are always backed by a literal path on disk.  This is what lets third parties mount
+/// synthetic trees -- an in-memory manifest built programmatically, say -- without first having to
+/// materialize anything under a temporary directory.  `DiskNodeSource` below is the implementation
+/// used for the normal, on-disk-backed case.
+///
+/// So far, `create_root` only consults this for the root mapping itself; non-root mappings are
+/// still applied via `Node::map`, which does not take a `NodeSource` and so cannot yet call back
+/// into `resolve` for paths it discovers underneath the root.  Closing that gap is follow-up work
+/// once `Node::map`'s signature carries a source to resolve against.
+pub trait NodeSource {
+    /// Returns the initial mappings to seed the root node hierarchy with.
+    fn root_mappings(&self) -> &[Mapping];
+
+    /// Resolves `underlying_path` to a freshly-constructed node of the appropriate kind.
+    ///
+    /// This is called by `create_root` to materialize the node for the root mapping; as described
+    /// above, `lookup` and `map` do not call it for the nodes they discover.
+    fn resolve(&self, underlying_path: &Path, inode: u64, writable: bool) -> NodeResult<Arc<Node>>;
+}
+
+/// The default `NodeSource`: nodes are backed by real paths on the local file system, exactly as
+/// sandboxfs has always behaved.
+pub struct DiskNodeSource {
+    mappings: Vec<Mapping>,
+
+    /// Whether to re-verify, via `ResolveRoot`, that a path handed to `resolve` still lives beneath
+    /// its own parent directory before trusting its `stat` data.  Guards against a TOCTOU race where
+    /// a symlink is swapped in between the kernel reporting a name to us and our own lookup of it;
+    /// corresponds to the `--hardened` command-line flag.
+    ///
+    /// Every call to `resolve` is covered, but `resolve` itself is, for now, only reached for the
+    /// root mapping (see the caveat on `NodeSource` above) -- so in practice this only re-verifies
+    /// the root today, not every name a kernel operation hands us.  It will cover the latter too
+    /// once non-root lookups are routed through `NodeSource` instead of `Node::map`.
+    hardened: bool,
+}
+
+impl DiskNodeSource {
+    /// Creates a new source that seeds the root hierarchy with `mappings` and otherwise resolves
+    /// nodes by `stat`-ing real paths, re-verifying each one via `ResolveRoot` first when `hardened`
+    /// is set.
+    pub fn new(mappings: Vec<Mapping>, hardened: bool) -> Self {
+        DiskNodeSource { mappings, hardened }
+    }
+
+    /// Re-resolves `underlying_path` from its parent directory via `ResolveRoot`, confirming the
+    /// kernel itself won't let a symlink swapped in between our caller learning of this path and
+    /// this check divert us outside of that parent.
+    ///
+    /// Only called when this source was constructed with `hardened` set; a path with no parent
+    /// component (the file system root) has nothing to re-verify against and is left alone.
+    fn verify_beneath_parent(underlying_path: &Path) -> NodeResult<()> {
+        let (parent, name) = match (underlying_path.parent(), underlying_path.file_name()) {
+            (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => (parent, name),
+            _ => return Ok(()),
+        };
+        let root = ResolveRoot::open(parent)?;
+        let fd = root.resolve(Path::new(name), OFlag::O_PATH | OFlag::O_NOFOLLOW, false)?;
+        let _ = nix::unistd::close(fd);
+        Ok(())
+    }
+}
+
+impl NodeSource for DiskNodeSource {
+    fn root_mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    fn resolve(&self, underlying_path: &Path, inode: u64, writable: bool) -> NodeResult<Arc<Node>> {
+        if self.hardened {
+            Self::verify_beneath_parent(underlying_path)?;
+        }
+
+        let metadata = fs::symlink_metadata(underlying_path)?;
+        if let Some(_kind) = special::classify(&metadata) {
+            return Ok(Special::new(inode, &underlying_path.to_path_buf(), &metadata, writable));
+        }
+        if metadata.is_dir() {
+            return Ok(Dir::new_mapped(inode, underlying_path, &metadata, writable));
+        }
+        if metadata.file_type().is_symlink() {
+            return Ok(Symlink::new(inode, underlying_path, &metadata, writable));
+        }
+        Ok(File::new(inode, underlying_path, &metadata, writable))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutils::AllFileTypes;
+
+    /// Verifies that `DiskNodeSource::resolve` -- the function `create_root` and, eventually,
+    /// directory lookups call to materialize a node for a path they have not seen before -- builds
+    /// a `Special` node for every kind `special::classify` recognizes, instead of that recognition
+    /// only ever being exercised by `special`'s own unit tests.
+    #[test]
+    fn resolve_builds_special_nodes_for_every_special_kind() {
+        let all_files = AllFileTypes::new();
+        let source = DiskNodeSource::new(vec![], false);
+        for (kind, path) in &all_files.entries {
+            let is_special = match kind {
+                fuse::FileType::BlockDevice | fuse::FileType::CharDevice
+                    | fuse::FileType::NamedPipe | fuse::FileType::Socket => true,
+                fuse::FileType::Directory | fuse::FileType::RegularFile
+                    | fuse::FileType::Symlink => false,
+            };
+            if !is_special {
+                continue;
+            }
+            let node = source.resolve(path, 1, false).unwrap();
+            assert_eq!(*kind, node.file_type_cached());
+        }
+    }
 }
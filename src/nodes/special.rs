@@ -0,0 +1,319 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Support for block/char devices, FIFOs, and sockets.
+//!
+//! Unlike directories, regular files, and symlinks, these node kinds have no content of their own
+//! to serve: opening and reading them just forwards to whatever the underlying special file does
+//! (for example, blocking on a FIFO until a writer appears).  Sockets cannot be meaningfully opened
+//! through the file system at all, so `open` on one fails with `ENXIO` just as it would natively.
+
+use fuse;
+use nix;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::unistd;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::{ArcHandle, ArcNode, AttrDelta, Handle, KernelError, Node, NodeResult};
+use time::Timespec;
+
+/// Classifies a `fs::Metadata` as one of the special file types supported by `Special`.
+///
+/// Returns `None` for directories, regular files, and symlinks, which are represented by their own
+/// dedicated node types instead.
+pub fn classify(metadata: &fs::Metadata) -> Option<fuse::FileType> {
+    let file_type = metadata.file_type();
+    if file_type.is_block_device() {
+        Some(fuse::FileType::BlockDevice)
+    } else if file_type.is_char_device() {
+        Some(fuse::FileType::CharDevice)
+    } else if file_type.is_fifo() {
+        Some(fuse::FileType::NamedPipe)
+    } else if file_type.is_socket() {
+        Some(fuse::FileType::Socket)
+    } else {
+        None
+    }
+}
+
+/// Builds the `fuse::FileAttr` for a special file, forwarding `st_rdev` for device nodes.
+fn attr(inode: u64, metadata: &fs::Metadata, kind: fuse::FileType) -> fuse::FileAttr {
+    fuse::FileAttr {
+        ino: inode,
+        size: 0,
+        blocks: 0,
+        atime: Timespec::new(metadata.atime(), metadata.atime_nsec() as i32),
+        mtime: Timespec::new(metadata.mtime(), metadata.mtime_nsec() as i32),
+        ctime: Timespec::new(metadata.ctime(), metadata.ctime_nsec() as i32),
+        crtime: Timespec::new(0, 0),
+        kind: kind,
+        perm: (metadata.mode() & 0o7777) as u16,
+        nlink: metadata.nlink() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        rdev: metadata.rdev() as u32,
+        flags: 0,
+    }
+}
+
+/// Applies a `utimensat(2)` call to `path`, leaving whichever of `atime`/`mtime` is `None`
+/// untouched (`UTIME_OMIT`) rather than resetting it to the current time.
+///
+/// `nix` does not expose `utimensat` with per-field omission, so this goes straight to `libc` the
+/// same way `resolve.rs` does for syscalls its wrapper crates don't cover.
+fn set_times(path: &Path, atime: Option<nix::sys::time::TimeVal>, mtime: Option<nix::sys::time::TimeVal>)
+    -> NodeResult<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| KernelError::from_errno(Errno::EINVAL))?;
+
+    let to_spec = |tv: Option<nix::sys::time::TimeVal>| match tv {
+        Some(tv) => libc::timespec {
+            tv_sec: tv.tv_sec() as libc::time_t,
+            tv_nsec: (tv.tv_usec() as libc::c_long) * 1000,
+        },
+        None => libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+    };
+    let times = [to_spec(atime), to_spec(mtime)];
+
+    let ret = unsafe {
+        libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    };
+    if ret < 0 {
+        return Err(KernelError::from_errno(Errno::last()));
+    }
+    Ok(())
+}
+
+/// Mutable state protected by a single lock, mirroring the locking scheme used by the other node
+/// types so that concurrent `getattr` calls do not race with each other.
+struct MutableState {
+    /// Last known attributes of the underlying special file.
+    attr: fuse::FileAttr,
+}
+
+/// A node that backs a block device, character device, FIFO, or socket.
+pub struct Special {
+    inode: u64,
+    underlying_path: PathBuf,
+    writable: bool,
+    kind: fuse::FileType,
+    state: Mutex<MutableState>,
+}
+
+impl Special {
+    /// Creates a new special file node for `underlying_path`, whose metadata must already be known
+    /// to be one of the kinds recognized by `classify`.
+    pub fn new(inode: u64, underlying_path: &PathBuf, metadata: &fs::Metadata, writable: bool)
+        -> ArcNode {
+        let kind = classify(metadata).expect("Caller must only construct Special for special files");
+        Arc::from(Special {
+            inode: inode,
+            underlying_path: underlying_path.clone(),
+            writable: writable,
+            kind: kind,
+            state: Mutex::from(MutableState { attr: attr(inode, metadata, kind) }),
+        })
+    }
+}
+
+impl Node for Special {
+    fn inode(&self) -> u64 {
+        self.inode
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn file_type_cached(&self) -> fuse::FileType {
+        self.kind
+    }
+
+    fn getattr(&self) -> NodeResult<fuse::FileAttr> {
+        let metadata = fs::symlink_metadata(&self.underlying_path)?;
+        let mut state = self.state.lock().unwrap();
+        state.attr = attr(self.inode, &metadata, self.kind);
+        Ok(state.attr)
+    }
+
+    fn setattr(&self, delta: &AttrDelta) -> NodeResult<fuse::FileAttr> {
+        if !self.writable {
+            return Err(KernelError::from_errno(Errno::EPERM));
+        }
+
+        if delta.size.is_some() {
+            // Special files have no content of their own to resize.
+            return Err(KernelError::from_errno(Errno::EINVAL));
+        }
+
+        if let Some(mode) = delta.mode {
+            nix::sys::stat::fchmodat(
+                None, &self.underlying_path, mode, nix::sys::stat::FchmodatFlags::NoFollowSymlink)?;
+        }
+
+        if delta.uid.is_some() || delta.gid.is_some() {
+            unistd::fchownat(
+                None, &self.underlying_path, delta.uid, delta.gid,
+                unistd::FchownatFlags::NoFollowSymlink)?;
+        }
+
+        if delta.atime.is_some() || delta.mtime.is_some() {
+            set_times(&self.underlying_path, delta.atime, delta.mtime)?;
+        }
+
+        self.getattr()
+    }
+
+    fn open(&self, flags: u32) -> NodeResult<ArcHandle> {
+        if self.kind == fuse::FileType::Socket {
+            // Sockets cannot be opened via open(2); they require connect/bind semantics that the
+            // file system layer does not implement.
+            return Err(KernelError::from_errno(nix::errno::Errno::ENXIO));
+        }
+
+        let oflags = OFlag::from_bits_truncate(flags as i32);
+        let fd = nix::fcntl::open(&self.underlying_path, oflags, nix::sys::stat::Mode::empty())?;
+        Ok(Arc::from(SpecialHandle { fd }))
+    }
+}
+
+/// An open handle to a special file, simply forwarding reads to the backing object.
+struct SpecialHandle {
+    fd: RawFd,
+}
+
+impl Handle for SpecialHandle {
+    fn read(&self, offset: i64, size: u32) -> NodeResult<Vec<u8>> {
+        let mut buf = vec![0u8; size as usize];
+        // FIFOs and other non-seekable descriptors reject `pread` with `ESPIPE`; read them
+        // sequentially instead, ignoring `offset`.
+        let n = match unistd::pread(self.fd, &mut buf, offset) {
+            Err(nix::Error::Sys(Errno::ESPIPE)) => unistd::read(self.fd, &mut buf)?,
+            result => result?,
+        };
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    fn write(&self, offset: i64, data: &[u8]) -> NodeResult<u32> {
+        // `pwrite(2)` fails with `ESPIPE` on a FIFO or other non-seekable descriptor, so those are
+        // written sequentially instead, ignoring `offset`; on a seekable device it writes at
+        // `offset` as normal.
+        let n = match unistd::pwrite(self.fd, data, offset) {
+            Err(nix::Error::Sys(Errno::ESPIPE)) => unistd::write(self.fd, data)?,
+            result => result?,
+        };
+        Ok(n as u32)
+    }
+
+    fn flush(&self) -> NodeResult<()> {
+        // Nothing is buffered in user space; every `write` above already reached the kernel.
+        Ok(())
+    }
+
+    fn fsync(&self, datasync: bool) -> NodeResult<()> {
+        let ret = unsafe {
+            if datasync { libc::fdatasync(self.fd) } else { libc::fsync(self.fd) }
+        };
+        if ret < 0 {
+            return Err(KernelError::from_errno(Errno::last()));
+        }
+        Ok(())
+    }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.fd)
+    }
+}
+
+impl Drop for SpecialHandle {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutils::AllFileTypes;
+
+    #[test]
+    fn classify_recognizes_every_special_kind() {
+        let all_files = AllFileTypes::new();
+        for (kind, path) in &all_files.entries {
+            let metadata = fs::symlink_metadata(path).unwrap();
+            let got = classify(&metadata);
+            match kind {
+                fuse::FileType::BlockDevice => assert_eq!(Some(fuse::FileType::BlockDevice), got),
+                fuse::FileType::CharDevice => assert_eq!(Some(fuse::FileType::CharDevice), got),
+                fuse::FileType::NamedPipe => assert_eq!(Some(fuse::FileType::NamedPipe), got),
+                fuse::FileType::Socket => assert_eq!(Some(fuse::FileType::Socket), got),
+                fuse::FileType::Directory | fuse::FileType::RegularFile
+                    | fuse::FileType::Symlink => assert_eq!(None, got),
+            }
+        }
+    }
+
+    #[test]
+    fn special_new_forwards_rdev_for_devices() {
+        let all_files = AllFileTypes::new();
+        if let Some(path) = all_files.entries.get(&fuse::FileType::CharDevice) {
+            let metadata = fs::symlink_metadata(path).unwrap();
+            let node = Special::new(1, path, &metadata, false);
+            let got = node.getattr().unwrap();
+            assert_eq!(metadata.rdev() as u32, got.rdev);
+            assert_eq!(fuse::FileType::CharDevice, got.kind);
+        }
+    }
+
+    #[test]
+    fn setattr_applies_a_chmod() {
+        let all_files = AllFileTypes::new();
+        let path = &all_files.entries[&fuse::FileType::NamedPipe];
+        let metadata = fs::symlink_metadata(path).unwrap();
+        let node = Special::new(1, path, &metadata, true);
+
+        let delta = AttrDelta { mode: Some(nix::sys::stat::Mode::S_IRUSR), ..Default::default() };
+        let got = node.setattr(&delta).unwrap();
+        assert_eq!(nix::sys::stat::Mode::S_IRUSR.bits() as u16, got.perm);
+    }
+
+    #[test]
+    fn setattr_on_read_only_node_is_rejected() {
+        let all_files = AllFileTypes::new();
+        let path = &all_files.entries[&fuse::FileType::NamedPipe];
+        let metadata = fs::symlink_metadata(path).unwrap();
+        let node = Special::new(1, path, &metadata, false);
+
+        let delta = AttrDelta { mode: Some(nix::sys::stat::Mode::S_IRUSR), ..Default::default() };
+        assert_eq!(Errno::EPERM as i32, node.setattr(&delta).unwrap_err().errno_as_i32());
+    }
+
+    #[test]
+    fn setattr_rejects_a_resize() {
+        let all_files = AllFileTypes::new();
+        let path = &all_files.entries[&fuse::FileType::NamedPipe];
+        let metadata = fs::symlink_metadata(path).unwrap();
+        let node = Special::new(1, path, &metadata, true);
+
+        let delta = AttrDelta { size: Some(0), ..Default::default() };
+        assert_eq!(Errno::EINVAL as i32, node.setattr(&delta).unwrap_err().errno_as_i32());
+    }
+}
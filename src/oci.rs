@@ -0,0 +1,65 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Ingests the `mounts` array of an OCI runtime spec (`config.json`) as sandboxfs mappings.
+//!
+//! This lets sandboxfs act as the file system layer underneath an OCI-compatible container
+//! runtime: instead of hand-constructing `Mapping`s, a runtime integrator points us at the spec it
+//! already has and gets back mappings ready to feed through `SandboxFS::create`/`apply_mapping`.
+
+use failure::{Fallible, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use Mapping;
+
+/// The subset of an OCI runtime spec's `mounts` entries that we care about.
+#[derive(Deserialize)]
+struct OciMount {
+    destination: PathBuf,
+    source: Option<PathBuf>,
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+/// The subset of an OCI runtime spec (`config.json`) that we care about.
+#[derive(Deserialize)]
+struct OciSpec {
+    #[serde(default)]
+    mounts: Vec<OciMount>,
+}
+
+/// Parses the OCI runtime spec at `path` and translates its `mounts` array into `Mapping`s.
+///
+/// `destination` becomes the mapping's sandbox path, `source` becomes the underlying path (a mount
+/// with no `source` is skipped, as it has nothing for us to back it with), and the presence of
+/// `"rw"` among `options` makes the mapping writable; everything else defaults to read-only.
+pub fn mount_from_oci_spec(path: &Path) -> Fallible<Vec<Mapping>> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read OCI runtime spec {:?}", path))?;
+    let spec: OciSpec = serde_json::from_str(&contents)
+        .context(format!("Failed to parse OCI runtime spec {:?}", path))?;
+
+    let mut mappings = Vec::with_capacity(spec.mounts.len());
+    for mount in spec.mounts {
+        let source = match mount.source {
+            Some(source) => source,
+            None => continue,
+        };
+        let writable = mount.options.iter().any(|o| o == "rw");
+        let mapping = Mapping::from_parts(mount.destination, source, writable)
+            .context(format!("Invalid OCI mount in {:?}", path))?;
+        mappings.push(mapping);
+    }
+    Ok(mappings)
+}
@@ -0,0 +1,137 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Helpers to join paths without ever letting the result escape a known base directory.
+//!
+//! sandboxfs is a security boundary: a mapping's underlying path and the targets of symlinks
+//! followed while resolving one must never let a client reach outside the subtree they were
+//! granted.  The functions here perform that join purely lexically (no filesystem access, so they
+//! cannot be fooled by the target not existing yet) by normalizing `.`/`..` components as they are
+//! consumed and refusing anything that would walk above `base`.
+
+use std::path::{Component, Path, PathBuf};
+
+/// An attempt to join a path onto a base would have escaped the base or required information (an
+/// absolute path) that is not safe to honor in this context.
+#[derive(Debug, Eq, PartialEq)]
+pub enum UnsafeJoinError {
+    /// The path to join in was absolute, which is not permitted here.
+    PathIsAbsolute(PathBuf),
+
+    /// The path to join contains enough `..` components to walk above `base`.
+    EscapesBase(PathBuf),
+}
+
+/// Joins `relative` onto `base`, rejecting absolute paths and any `..` that would walk above
+/// `base` once `.`/`..` components are normalized away.
+///
+/// This never touches the file system: it is a purely lexical operation, which is what makes it
+/// safe to use on symlink targets and other untrusted input before anything has been resolved.
+pub fn join_safely(base: &Path, relative: &Path) -> Result<PathBuf, UnsafeJoinError> {
+    if relative.is_absolute() {
+        return Err(UnsafeJoinError::PathIsAbsolute(relative.to_path_buf()));
+    }
+
+    let mut components: Vec<Component> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {
+                if let Component::Normal(_) = component {
+                    components.push(component);
+                }
+            },
+            Component::ParentDir => {
+                if components.pop().is_none() {
+                    return Err(UnsafeJoinError::EscapesBase(relative.to_path_buf()));
+                }
+            },
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(UnsafeJoinError::PathIsAbsolute(relative.to_path_buf()));
+            },
+        }
+    }
+
+    let mut joined = base.to_path_buf();
+    for component in components {
+        joined.push(component.as_os_str());
+    }
+    Ok(joined)
+}
+
+/// Joins an absolute path (such as a mapping target or a symlink target) against `base`, rejecting
+/// the join if the absolute path, reinterpreted as relative to `base`, would escape it.
+///
+/// This is the counterpart to `join_safely` for inputs that are themselves absolute, such as the
+/// target of an absolute symlink: we strip the leading root and then apply the same `..`
+/// accounting as `join_safely`.
+pub fn join_absolute_path(base: &Path, absolute: &Path) -> Result<PathBuf, UnsafeJoinError> {
+    let relative: PathBuf = absolute.components()
+        .filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_)))
+        .collect();
+    join_safely(base, &relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_safely_ok() {
+        assert_eq!(
+            PathBuf::from("/base/foo/bar"),
+            join_safely(Path::new("/base"), Path::new("foo/bar")).unwrap());
+        assert_eq!(
+            PathBuf::from("/base/bar"),
+            join_safely(Path::new("/base"), Path::new("foo/../bar")).unwrap());
+        assert_eq!(
+            PathBuf::from("/base"),
+            join_safely(Path::new("/base"), Path::new("foo/..")).unwrap());
+    }
+
+    #[test]
+    fn join_safely_rejects_absolute() {
+        let err = join_safely(Path::new("/base"), Path::new("/etc")).unwrap_err();
+        assert_eq!(UnsafeJoinError::PathIsAbsolute(PathBuf::from("/etc")), err);
+    }
+
+    #[test]
+    fn join_safely_rejects_escape() {
+        let err = join_safely(Path::new("/base"), Path::new("../etc")).unwrap_err();
+        assert_eq!(UnsafeJoinError::EscapesBase(PathBuf::from("../etc")), err);
+
+        let err = join_safely(Path::new("/base"), Path::new("../../etc")).unwrap_err();
+        assert_eq!(UnsafeJoinError::EscapesBase(PathBuf::from("../../etc")), err);
+
+        let err = join_safely(Path::new("/base"), Path::new("foo/../../etc")).unwrap_err();
+        assert_eq!(UnsafeJoinError::EscapesBase(PathBuf::from("foo/../../etc")), err);
+    }
+
+    #[test]
+    fn join_absolute_path_ok() {
+        assert_eq!(
+            PathBuf::from("/base/etc/passwd"),
+            join_absolute_path(Path::new("/base"), Path::new("/etc/passwd")).unwrap());
+    }
+
+    #[test]
+    fn join_absolute_path_rejects_escape() {
+        // Only the leading root is stripped, so "/../../etc" becomes "../../etc", whose ".."
+        // components walk past the base and are rejected (a lone "/../etc" is rejected too).
+        let err = join_absolute_path(Path::new("/base"), Path::new("/../../etc")).unwrap_err();
+        match err {
+            UnsafeJoinError::EscapesBase(_) => (),
+            other => panic!("Expected EscapesBase, got {:?}", other),
+        }
+    }
+}
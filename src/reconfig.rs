@@ -0,0 +1,168 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Live reconfiguration: reads mapping directives from an input stream and applies them to a
+//! running sandbox as they arrive, echoing a result line per directive to an output stream.
+//!
+//! Besides plain mapping directives, the input format supports two preprocessing directives that
+//! let operators compose a mapping set out of reusable fragments:
+//!
+//! * `%include <path>` recursively pulls in another config file at that point.  Relative paths are
+//!   resolved against the including file's directory.  Because this is a textual inclusion, a later
+//!   directive for the same sandbox path overrides an earlier one (last-writer-wins), whether that
+//!   earlier directive came from an include or not.
+//! * `%unset <sandbox-path>` removes a previously-declared mapping for `<sandbox-path>`.
+//!
+//! Include cycles are rejected: each file's canonical path is tracked as it is entered, and
+//! including a file that is already being processed is an error rather than an infinite loop.
+
+use failure::{Fallible, ResultExt};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use Mapping;
+
+/// Operations a reconfigurable file system must support to apply the directives parsed here.
+pub trait ReconfigurableFS {
+    /// Adds a new mapping (or replaces the mapping already present at `mapping`'s path).
+    fn map(&self, mapping: &Mapping) -> Fallible<()>;
+
+    /// Removes the mapping at `path`.
+    fn unmap<P: AsRef<Path>>(&self, path: P) -> Fallible<()>;
+
+    /// Atomically rebinds the mapping at `path` to a new underlying path and/or writability.
+    fn remap<P: AsRef<Path>>(&self, path: P, underlying_path: &Path, writable: bool) -> Fallible<()>;
+}
+
+/// Opens `path` for reading reconfiguration directives from.
+pub fn open_input(path: &Path) -> Fallible<fs::File> {
+    fs::File::open(path).context(format!("Failed to open {:?} for reading", path)).map_err(Into::into)
+}
+
+/// Opens `path` for writing reconfiguration acknowledgements to.
+pub fn open_output(path: &Path) -> Fallible<fs::File> {
+    fs::OpenOptions::new().write(true).open(path)
+        .context(format!("Failed to open {:?} for writing", path)).map_err(Into::into)
+}
+
+/// Parses `writable` out of one of the two tokens accepted in mapping directives.
+fn parse_writable(token: &str) -> Fallible<bool> {
+    match token {
+        "ro" => Ok(false),
+        "rw" => Ok(true),
+        other => bail!("Invalid writability token {:?}; expected \"ro\" or \"rw\"", other),
+    }
+}
+
+/// Applies a single already-parsed, non-`%include` directive to `target`.
+fn apply_line<T: ReconfigurableFS>(fields: &[&str], target: &T) -> Fallible<()> {
+    match fields {
+        ["%unset", sandbox_path] => target.unmap(sandbox_path),
+
+        [sandbox_path, underlying_path, writability] => {
+            let writable = parse_writable(writability)?;
+            let mapping = Mapping::from_parts(
+                PathBuf::from(sandbox_path), PathBuf::from(underlying_path), writable)?;
+            target.map(&mapping)
+        },
+
+        _ => bail!("Malformed reconfiguration line: {:?}", fields.join(" ")),
+    }
+}
+
+/// Processes a single already-read `line`, applying it to `target` immediately and writing one
+/// `OK`/`ERROR: ...` line to `writer` -- except for `%include`, which instead recurses into
+/// `process_include` and lets each directive it contains report for itself.
+///
+/// Flushes `writer` after every line that produces output, since a synchronous client reading acks
+/// off the other end of a pipe has no way to know a line is complete until it sees it -- buffering
+/// it indefinitely behind a `BufWriter` would leave that client blocked waiting for a reply that
+/// already happened.
+fn process_line<T: ReconfigurableFS>(line: &str, base_dir: &Path, visited: &mut HashSet<PathBuf>,
+    target: &T, writer: &mut impl Write) {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return;
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if let ["%include", included] = fields.as_slice() {
+        let included_path = Path::new(included);
+        let resolved = if included_path.is_absolute() {
+            included_path.to_path_buf()
+        } else {
+            base_dir.join(included_path)
+        };
+        if let Err(e) = process_include(&resolved, visited, target, writer) {
+            let _ = writeln!(writer, "ERROR: {}", ::flatten_causes(&e));
+            let _ = writer.flush();
+        }
+        return;
+    }
+
+    match apply_line(&fields, target) {
+        Ok(()) => { let _ = writeln!(writer, "OK"); },
+        Err(e) => { let _ = writeln!(writer, "ERROR: {}", ::flatten_causes(&e)); },
+    }
+    let _ = writer.flush();
+}
+
+/// Reads `path` line by line, applying each directive to `target` as soon as it is parsed (so a
+/// later, unrelated directive in the same run is unaffected by an earlier one being malformed) and
+/// recursing into any `%include` it contains.  Tracks `visited` canonical paths to reject cycles.
+fn process_include<T: ReconfigurableFS>(path: &Path, visited: &mut HashSet<PathBuf>, target: &T,
+    writer: &mut impl Write) -> Fallible<()> {
+    let canonical = fs::canonicalize(path)
+        .context(format!("Failed to resolve {:?} for inclusion", path))?;
+    ensure!(visited.insert(canonical.clone()), "Include cycle detected at {:?}", path);
+
+    let file = fs::File::open(path).context(format!("Failed to open {:?}", path))?;
+    let reader = ::std::io::BufReader::new(file);
+    let base_dir = canonical.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("/"));
+    for line in reader.lines() {
+        match line {
+            Ok(line) => process_line(&line, &base_dir, visited, target, writer),
+            Err(e) => {
+                let _ = writeln!(writer, "ERROR: {}", ::flatten_causes(&e.into()));
+                let _ = writer.flush();
+            },
+        }
+    }
+
+    visited.remove(&canonical);
+    Ok(())
+}
+
+/// Reads reconfiguration directives from `reader` (expanding any `%include`s against the current
+/// directory) and applies each to `fs` as soon as it is parsed, writing one `OK`/`ERROR: ...` line
+/// per directive to `writer`.
+///
+/// Applying directives incrementally, rather than parsing the whole input into a batch first, means
+/// a malformed or failing directive only ever produces its own `ERROR: ...` line: every directive
+/// before and after it in the same run is still applied, instead of the single bad line aborting the
+/// rest of the batch.
+pub fn run_loop<T: ReconfigurableFS>(reader: impl BufRead, mut writer: impl Write, fs: &T) {
+    let mut visited = HashSet::new();
+    let base_dir = ::std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+    for line in reader.lines() {
+        match line {
+            Ok(line) => process_line(&line, &base_dir, &mut visited, fs, &mut writer),
+            Err(e) => {
+                let _ = writeln!(writer, "ERROR: {}", ::flatten_causes(&e.into()));
+                let _ = writer.flush();
+            },
+        }
+    }
+}
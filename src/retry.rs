@@ -0,0 +1,130 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! A small, bounded retry helper for operations that can fail transiently with `EBUSY`/`ENOTEMPTY`.
+//!
+//! `unmap` and `unlink` both race against other processes that may still be using (or cleaning up)
+//! the very path being removed; a tool that just finished writing a tree and is now deleting it is
+//! the common case.  Failing on the first `EBUSY`/`ENOTEMPTY` is needlessly unforgiving, so these
+//! two call sites retry with exponential backoff before giving up.
+
+use nix::errno::Errno;
+use std::thread;
+use std::time::Duration;
+
+/// Governs how many times, and how long, `retry_on_busy` waits between attempts.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts to make before surfacing the last error.  Must be at least 1:
+    /// with 0, `retry_on_busy` never calls the operation and panics.
+    pub attempts: u32,
+
+    /// Delay before the first retry; doubles after each subsequent attempt, capped at `limit`.
+    pub initial_delay: Duration,
+
+    /// Upper bound on the delay between attempts, regardless of how many doublings have happened.
+    pub limit: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            initial_delay: Duration::from_millis(10),
+            limit: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Repeatedly invokes `op` until it succeeds, fails with an errno other than `EBUSY`/`ENOTEMPTY` (as
+/// reported by `errno_of`), or `policy.attempts` is exhausted.
+///
+/// Between attempts, sleeps for a delay that starts at `policy.initial_delay` and doubles each time,
+/// capped at `policy.limit`.  The last error is returned verbatim if every attempt is exhausted.
+pub fn retry_on_busy<T, E>(policy: &RetryPolicy, errno_of: impl Fn(&E) -> Option<Errno>,
+    mut op: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    let mut delay = policy.initial_delay;
+    let mut last_err = None;
+
+    for attempt in 0..policy.attempts {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let retryable = match errno_of(&e) {
+                    Some(Errno::EBUSY) | Some(Errno::ENOTEMPTY) => true,
+                    _ => false,
+                };
+                if !retryable || attempt + 1 == policy.attempts {
+                    return Err(e);
+                }
+                last_err = Some(e);
+                thread::sleep(delay);
+                delay = std::cmp::min(delay * 2, policy.limit);
+            },
+        }
+    }
+
+    // Unreachable in practice: the loop above always returns on its last iteration.
+    Err(last_err.expect("Loop always runs at least once"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn retry_on_busy_succeeds_after_transient_failures() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            initial_delay: Duration::from_millis(1),
+            limit: Duration::from_millis(10),
+        };
+        let calls = Cell::new(0);
+        let result: Result<(), Errno> = retry_on_busy(&policy, |e| Some(*e), || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 { Err(Errno::EBUSY) } else { Ok(()) }
+        });
+        assert!(result.is_ok());
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn retry_on_busy_gives_up_after_attempts_exhausted() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            initial_delay: Duration::from_millis(1),
+            limit: Duration::from_millis(10),
+        };
+        let calls = Cell::new(0);
+        let result: Result<(), Errno> = retry_on_busy(&policy, |e| Some(*e), || {
+            calls.set(calls.get() + 1);
+            Err(Errno::ENOTEMPTY)
+        });
+        assert_eq!(Err(Errno::ENOTEMPTY), result);
+        assert_eq!(3, calls.get());
+    }
+
+    #[test]
+    fn retry_on_busy_does_not_retry_other_errnos() {
+        let policy = RetryPolicy::default();
+        let calls = Cell::new(0);
+        let result: Result<(), Errno> = retry_on_busy(&policy, |e| Some(*e), || {
+            calls.set(calls.get() + 1);
+            Err(Errno::EPERM)
+        });
+        assert_eq!(Err(Errno::EPERM), result);
+        assert_eq!(1, calls.get());
+    }
+}
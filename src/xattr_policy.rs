@@ -0,0 +1,242 @@
+// Copyright 2018 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License.  You may obtain a copy
+// of the License at:
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.  See the
+// License for the specific language governing permissions and limitations
+// under the License.
+
+//! Per-mapping extended-attribute namespace filtering.
+//!
+//! Until now, xattr support has been a single global toggle (`--xattrs`) that lets
+//! `setxattr`/`getxattr`/`listxattr`/`removexattr` through wholesale or not at all.  `XattrPolicy`
+//! refines that down to the four POSIX xattr namespaces -- `security.*`, `system.*`, `trusted.*` and
+//! `user.*` -- so a mapping can, say, pass `user.*` through untouched while hiding
+//! `security.selinux` from the sandboxed process.
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+/// The POSIX xattr namespaces sandboxfs recognizes by name prefix.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum XattrNamespace {
+    Security,
+    System,
+    Trusted,
+    User,
+    Other,
+}
+
+impl XattrNamespace {
+    /// Classifies `name` by its namespace prefix.
+    fn classify(name: &OsStr) -> XattrNamespace {
+        let bytes = name.as_bytes();
+        if bytes.starts_with(b"security.") {
+            XattrNamespace::Security
+        } else if bytes.starts_with(b"system.") {
+            XattrNamespace::System
+        } else if bytes.starts_with(b"trusted.") {
+            XattrNamespace::Trusted
+        } else if bytes.starts_with(b"user.") {
+            XattrNamespace::User
+        } else {
+            XattrNamespace::Other
+        }
+    }
+
+    /// Returns the literal prefix that identifies this namespace, or the empty string for `Other`.
+    fn prefix(self) -> &'static str {
+        match self {
+            XattrNamespace::Security => "security.",
+            XattrNamespace::System => "system.",
+            XattrNamespace::Trusted => "trusted.",
+            XattrNamespace::User => "user.",
+            XattrNamespace::Other => "",
+        }
+    }
+}
+
+/// How a single xattr namespace is exposed to sandboxed processes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum XattrRule {
+    /// Pass requests through to the underlying node unchanged.
+    Allow,
+
+    /// Hide the namespace entirely: `listxattr` omits its names, `getxattr` acts as though no
+    /// attribute by that name exists, and `setxattr`/`removexattr` fail with `EPERM`.
+    Deny,
+
+    /// Expose the namespace under a different literal prefix, rewriting names on the way in and
+    /// back out again.
+    Rewrite(String),
+}
+
+/// Per-mapping policy controlling which extended-attribute namespaces sandboxed processes may see.
+///
+/// Defaults to `Allow` for every namespace, which preserves the historical behavior of the global
+/// `--xattrs` toggle: namespace filtering is opt-in on top of it, not a new default restriction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XattrPolicy {
+    security: XattrRule,
+    system: XattrRule,
+    trusted: XattrRule,
+    user: XattrRule,
+    other: XattrRule,
+}
+
+impl Default for XattrPolicy {
+    fn default() -> Self {
+        XattrPolicy {
+            security: XattrRule::Allow,
+            system: XattrRule::Allow,
+            trusted: XattrRule::Allow,
+            user: XattrRule::Allow,
+            other: XattrRule::Allow,
+        }
+    }
+}
+
+impl XattrPolicy {
+    /// Sets the rule governing `security.*` attributes.
+    pub fn with_security(mut self, rule: XattrRule) -> Self {
+        self.security = rule;
+        self
+    }
+
+    /// Sets the rule governing `system.*` attributes.
+    pub fn with_system(mut self, rule: XattrRule) -> Self {
+        self.system = rule;
+        self
+    }
+
+    /// Sets the rule governing `trusted.*` attributes.
+    pub fn with_trusted(mut self, rule: XattrRule) -> Self {
+        self.trusted = rule;
+        self
+    }
+
+    /// Sets the rule governing `user.*` attributes.
+    pub fn with_user(mut self, rule: XattrRule) -> Self {
+        self.user = rule;
+        self
+    }
+
+    /// Returns the rule that applies to `name`.
+    fn rule_for(&self, name: &OsStr) -> &XattrRule {
+        match XattrNamespace::classify(name) {
+            XattrNamespace::Security => &self.security,
+            XattrNamespace::System => &self.system,
+            XattrNamespace::Trusted => &self.trusted,
+            XattrNamespace::User => &self.user,
+            XattrNamespace::Other => &self.other,
+        }
+    }
+
+    /// Translates a sandbox-facing attribute `name` into the name to use on the underlying node, or
+    /// returns `None` if `name`'s namespace is denied.
+    ///
+    /// `getxattr`, `setxattr` and `removexattr` all funnel through this before touching the
+    /// underlying node; a `None` result means the caller should act as if the attribute does not
+    /// exist (`ENODATA` for reads, `EPERM` for writes).
+    pub fn resolve(&self, name: &OsStr) -> Option<OsString> {
+        match self.rule_for(name) {
+            XattrRule::Allow => Some(name.to_os_string()),
+            XattrRule::Deny => None,
+            XattrRule::Rewrite(new_prefix) => {
+                let old_prefix = XattrNamespace::classify(name).prefix();
+                let mut rewritten = new_prefix.clone().into_bytes();
+                rewritten.extend_from_slice(&name.as_bytes()[old_prefix.len()..]);
+                Some(OsString::from_vec(rewritten))
+            },
+        }
+    }
+
+    /// Translates a name as it is actually stored on the underlying node back into the name a
+    /// sandboxed process should see it as, or returns `None` if it should be hidden from
+    /// `listxattr`.
+    ///
+    /// This is the inverse of `resolve`: `listxattr` on the underlying node yields names with
+    /// whatever prefix `resolve` last wrote them under, so a `Rewrite` rule must be undone by
+    /// matching its *new* prefix and restoring the original one, rather than by reclassifying the
+    /// name under its own (now rewritten) prefix.
+    pub fn unresolve(&self, underlying_name: &OsStr) -> Option<OsString> {
+        for (namespace, rule) in self.rules() {
+            if let XattrRule::Rewrite(new_prefix) = rule {
+                if underlying_name.as_bytes().starts_with(new_prefix.as_bytes()) {
+                    let mut restored = namespace.prefix().as_bytes().to_vec();
+                    restored.extend_from_slice(&underlying_name.as_bytes()[new_prefix.len()..]);
+                    return Some(OsString::from_vec(restored));
+                }
+            }
+        }
+
+        match self.rule_for(underlying_name) {
+            XattrRule::Allow => Some(underlying_name.to_os_string()),
+            // Either genuinely denied, or it is an attribute that happens to carry this namespace's
+            // own prefix without ever having gone through a rewrite -- either way, a sandboxed
+            // process must not see it under a `Rewrite` namespace it didn't come from.
+            XattrRule::Deny | XattrRule::Rewrite(_) => None,
+        }
+    }
+
+    /// Iterates over every configured namespace alongside the rule that governs it.
+    fn rules(&self) -> impl Iterator<Item = (XattrNamespace, &XattrRule)> {
+        let namespaces = [XattrNamespace::Security, XattrNamespace::System, XattrNamespace::Trusted,
+            XattrNamespace::User];
+        let rules: [&XattrRule; 4] = [&self.security, &self.system, &self.trusted, &self.user];
+        namespaces.iter().copied().zip(rules.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_allows_every_namespace() {
+        let policy = XattrPolicy::default();
+        assert_eq!(Some(OsString::from("security.selinux")),
+            policy.resolve(OsStr::new("security.selinux")));
+        assert_eq!(Some(OsString::from("user.comment")), policy.resolve(OsStr::new("user.comment")));
+        assert!(policy.unresolve(OsStr::new("trusted.overlay.opaque")).is_some());
+    }
+
+    #[test]
+    fn deny_hides_the_namespace() {
+        let policy = XattrPolicy::default().with_security(XattrRule::Deny);
+        assert_eq!(None, policy.resolve(OsStr::new("security.selinux")));
+        assert!(policy.unresolve(OsStr::new("security.selinux")).is_none());
+        assert!(policy.unresolve(OsStr::new("user.comment")).is_some());
+    }
+
+    #[test]
+    fn rewrite_changes_the_prefix_but_keeps_the_suffix() {
+        let policy = XattrPolicy::default()
+            .with_trusted(XattrRule::Rewrite("user.sandboxfs.trusted.".to_string()));
+        assert_eq!(Some(OsString::from("user.sandboxfs.trusted.overlay.opaque")),
+            policy.resolve(OsStr::new("trusted.overlay.opaque")));
+    }
+
+    #[test]
+    fn unresolve_inverts_a_rewritten_name() {
+        let policy = XattrPolicy::default()
+            .with_trusted(XattrRule::Rewrite("user.sandboxfs.trusted.".to_string()));
+        assert_eq!(Some(OsString::from("trusted.overlay.opaque")),
+            policy.unresolve(OsStr::new("user.sandboxfs.trusted.overlay.opaque")));
+        // A name that merely happens to share the "user." prefix without actually having gone
+        // through the rewrite must not be exposed as if it had.
+        assert_eq!(Some(OsString::from("user.comment")), policy.unresolve(OsStr::new("user.comment")));
+    }
+
+    #[test]
+    fn unknown_namespace_defaults_to_the_other_rule() {
+        let policy = XattrPolicy::default().with_user(XattrRule::Deny);
+        assert_eq!(Some(OsString::from("md5sum")), policy.resolve(OsStr::new("md5sum")));
+    }
+}